@@ -10,6 +10,7 @@ fn main() {
         .with_language(cbindgen::Language::C)
         .with_include("mongoc-rust-uri.h")
         .with_include("mongoc-rust-error.h")
+        .with_include("mongoc-rust-read-concern.h")
         .generate()
         .expect("Unable to generate bindings")
         .write_to_file("bindings.h");
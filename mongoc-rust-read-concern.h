@@ -0,0 +1,5 @@
+#define MONGOC_READ_CONCERN_LEVEL_AVAILABLE "available"
+#define MONGOC_READ_CONCERN_LEVEL_LOCAL "local"
+#define MONGOC_READ_CONCERN_LEVEL_MAJORITY "majority"
+#define MONGOC_READ_CONCERN_LEVEL_LINEARIZABLE "linearizable"
+#define MONGOC_READ_CONCERN_LEVEL_SNAPSHOT "snapshot"
@@ -2,6 +2,8 @@ use std::ops::{Deref, DerefMut};
 
 use mongodb::options::{ServerApi, ServerApiVersion};
 
+use crate::error::catch_panic;
+
 pub struct mongoc_server_api_t {
     rust_server_api: ServerApi,
 }
@@ -43,8 +45,10 @@ impl From<mongoc_server_api_version_t> for ServerApiVersion {
 pub unsafe extern "C" fn mongoc_server_api_new(
     version: mongoc_server_api_version_t,
 ) -> *mut mongoc_server_api_t {
-    let sa = ServerApi::builder().version(version).build();
-    Box::into_raw(Box::new(sa.into()))
+    catch_panic(std::ptr::null_mut(), || {
+        let sa = ServerApi::builder().version(version).build();
+        Box::into_raw(Box::new(sa.into()))
+    })
 }
 
 #[no_mangle]
@@ -52,15 +56,17 @@ pub unsafe extern "C" fn mongoc_server_api_deprecation_errors(
     api: *mut mongoc_server_api_t,
     de: bool,
 ) {
-    (*api).deprecation_errors = Some(de)
+    catch_panic(std::ptr::null_mut(), || {
+        (*api).deprecation_errors = Some(de)
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_server_api_strict(api: *mut mongoc_server_api_t, strict: bool) {
-    (*api).strict = Some(strict)
+    catch_panic(std::ptr::null_mut(), || (*api).strict = Some(strict))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_server_api_destroy(api: *mut mongoc_server_api_t) {
-    drop(Box::from_raw(api))
+    catch_panic(std::ptr::null_mut(), || drop(Box::from_raw(api)))
 }
@@ -1,6 +1,10 @@
 use std::os::raw::c_char;
 
-use crate::bson::{bson_oid_t, bson_t};
+use crate::{
+    bson::{bson_oid_t, bson_t},
+    error::catch_panic,
+    host_list::mongoc_host_list_t,
+};
 
 pub struct mongoc_apm_command_started_t {}
 
@@ -8,61 +12,60 @@ pub struct mongoc_apm_command_started_t {}
 pub unsafe extern "C" fn mongoc_apm_command_started_get_context(
     _event: *const mongoc_apm_command_started_t,
 ) -> *const u8 {
-    std::ptr::null()
+    catch_panic(std::ptr::null_mut(), || std::ptr::null())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_command(
     _event: *const mongoc_apm_command_started_t,
 ) -> *const bson_t<'static> {
-    std::ptr::null()
+    catch_panic(std::ptr::null_mut(), || std::ptr::null())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_database_name(
     _event: *const mongoc_apm_command_started_t,
 ) -> *const c_char {
-    "placeholder".as_ptr() as *const c_char
+    catch_panic(std::ptr::null_mut(), || {
+        "placeholder".as_ptr() as *const c_char
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_command_name(
     _event: *const mongoc_apm_command_started_t,
 ) -> *const c_char {
-    "placeholder".as_ptr() as *const c_char
+    catch_panic(std::ptr::null_mut(), || {
+        "placeholder".as_ptr() as *const c_char
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_request_id(
     _event: *const mongoc_apm_command_started_t,
 ) -> i64 {
-    12
+    catch_panic(std::ptr::null_mut(), || 12)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_operation_id(
     _event: *const mongoc_apm_command_started_t,
 ) -> i64 {
-    12
+    catch_panic(std::ptr::null_mut(), || 12)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_host(
     _event: *const mongoc_apm_command_started_t,
 ) -> *const mongoc_host_list_t {
-    std::ptr::null()
+    catch_panic(std::ptr::null_mut(), || std::ptr::null())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_started_get_service_id(
     _event: *const mongoc_apm_command_started_t,
 ) -> *const bson_oid_t {
-    std::ptr::null()
-}
-
-#[repr(C)]
-pub struct mongoc_host_list_t {
-    next: *const u8,
+    catch_panic(std::ptr::null_mut(), || std::ptr::null())
 }
 
 pub struct mongoc_apm_command_succeeded_t {}
@@ -71,24 +74,24 @@ pub struct mongoc_apm_command_succeeded_t {}
 pub unsafe extern "C" fn mongoc_apm_command_succeeded_get_context(
     _event: *const mongoc_apm_command_succeeded_t,
 ) -> *const u8 {
-    std::ptr::null()
+    catch_panic(std::ptr::null_mut(), || std::ptr::null())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_succeeded_get_duration(
     _event: *const mongoc_apm_command_succeeded_t,
 ) -> i64 {
-    12
+    catch_panic(std::ptr::null_mut(), || 12)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_apm_command_succeeded_get_reply(
     _event: *const mongoc_apm_command_succeeded_t,
 ) -> *const bson_t<'static> {
-    std::ptr::null()
+    catch_panic(std::ptr::null_mut(), || std::ptr::null())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_server_description_destroy(_sd: *mut u8) {
-    panic!("sd not implemented")
+    // Server descriptions are never handed out yet, so there's nothing to free.
 }
@@ -7,11 +7,14 @@ use std::{
 };
 
 use anyhow::Result;
+use bitflags::bitflags;
 use mongodb::bson::{
     oid::ObjectId, DateTime, DbPointer, Decimal128, Document, RawArrayBuf, RawBinaryRef, RawBson,
     RawBsonRef, RawDocument, RawDocumentBuf, RawJavaScriptCodeWithScope, RawRegexRef, Timestamp,
 };
 
+use crate::error::catch_panic;
+
 #[allow(non_camel_case_types)]
 pub struct bson_t<'a> {
     pub(crate) doc: Cow<'a, RawDocument>,
@@ -39,6 +42,83 @@ impl<'a> bson_t<'a> {
     }
 }
 
+bitflags! {
+    #[allow(non_camel_case_types)]
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct bson_validate_flags_t: u32 {
+        const BSON_VALIDATE_NONE = 0;
+        const BSON_VALIDATE_UTF8 = 1 << 0;
+        const BSON_VALIDATE_DOLLAR_KEYS = 1 << 1;
+        const BSON_VALIDATE_DOT_KEYS = 1 << 2;
+        const BSON_VALIDATE_UTF8_ALLOW_NULL = 1 << 3;
+        const BSON_VALIDATE_EMPTY_KEYS = 1 << 4;
+    }
+}
+
+impl bson_validate_flags_t {
+    /// The validation libmongoc applies by default to inserted and replacement documents.
+    pub(crate) fn default_insert() -> Self {
+        Self::BSON_VALIDATE_UTF8
+            | Self::BSON_VALIDATE_UTF8_ALLOW_NULL
+            | Self::BSON_VALIDATE_EMPTY_KEYS
+            | Self::BSON_VALIDATE_DOT_KEYS
+            | Self::BSON_VALIDATE_DOLLAR_KEYS
+    }
+
+    /// The validation libmongoc applies by default to update documents.
+    pub(crate) fn default_update() -> Self {
+        Self::BSON_VALIDATE_UTF8
+            | Self::BSON_VALIDATE_UTF8_ALLOW_NULL
+            | Self::BSON_VALIDATE_EMPTY_KEYS
+    }
+}
+
+/// Checks a document and its subdocuments against `flags`, as bson_validate_with_error does.
+pub(crate) fn validate(doc: &RawDocument, flags: bson_validate_flags_t) -> Result<()> {
+    for (i, kvp) in doc.into_iter().enumerate() {
+        let (key, value) = kvp?;
+
+        if flags.contains(bson_validate_flags_t::BSON_VALIDATE_EMPTY_KEYS) && key.is_empty() {
+            anyhow::bail!("empty key");
+        }
+        // DBRefs are the one place keys may begin with '$'.
+        let dbref_key = matches!(key, "$id" | "$db") || (i == 0 && key == "$ref");
+        if flags.contains(bson_validate_flags_t::BSON_VALIDATE_DOLLAR_KEYS)
+            && key.starts_with('$')
+            && !dbref_key
+        {
+            anyhow::bail!("keys cannot begin with \"$\": \"{}\"", key);
+        }
+        if flags.contains(bson_validate_flags_t::BSON_VALIDATE_DOT_KEYS) && key.contains('.') {
+            anyhow::bail!("keys cannot contain \".\": \"{}\"", key);
+        }
+
+        validate_value(key, value, flags)?;
+    }
+    Ok(())
+}
+
+fn validate_value(key: &str, value: RawBsonRef, flags: bson_validate_flags_t) -> Result<()> {
+    match value {
+        RawBsonRef::String(s)
+            if flags.contains(bson_validate_flags_t::BSON_VALIDATE_UTF8)
+                && !flags.contains(bson_validate_flags_t::BSON_VALIDATE_UTF8_ALLOW_NULL)
+                && s.contains('\0') =>
+        {
+            anyhow::bail!("invalid utf-8 string for key \"{}\"", key)
+        }
+        RawBsonRef::Document(d) => validate(d, flags),
+        RawBsonRef::Array(a) => {
+            for value in a {
+                validate_value(key, value?, flags)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
 impl<'a> From<RawDocumentBuf> for bson_t<'a> {
     fn from(doc: RawDocumentBuf) -> Self {
         Self { doc: doc.into() }
@@ -53,7 +133,9 @@ impl<'a> From<&'a RawDocument> for bson_t<'a> {
 
 #[no_mangle]
 pub unsafe extern "C" fn bson_new() -> *mut bson_t<'static> {
-    Box::into_raw(Box::new(RawDocumentBuf::new().into()))
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(RawDocumentBuf::new().into()))
+    })
 }
 
 #[no_mangle]
@@ -62,13 +144,15 @@ pub unsafe extern "C" fn bson_init_static<'a>(
     bytes: *const u8,
     length: usize,
 ) -> bool {
-    let slice = std::slice::from_raw_parts(bytes, length);
-    if let Ok(doc) = RawDocument::from_bytes(slice) {
-        (*bson).doc = Cow::Borrowed(doc);
-        true
-    } else {
-        false
-    }
+    catch_panic(std::ptr::null_mut(), || {
+        let slice = std::slice::from_raw_parts(bytes, length);
+        if let Ok(doc) = RawDocument::from_bytes(slice) {
+            (*bson).doc = Cow::Borrowed(doc);
+            true
+        } else {
+            false
+        }
+    })
 }
 
 #[no_mangle]
@@ -78,7 +162,7 @@ pub unsafe extern "C" fn bson_append_int32(
     _key_length: isize,
     val: i32,
 ) -> bool {
-    (*bson).append(key, val)
+    catch_panic(std::ptr::null_mut(), || (*bson).append(key, val))
 }
 
 #[no_mangle]
@@ -88,7 +172,7 @@ pub unsafe extern "C" fn bson_append_int64(
     _key_length: isize,
     val: i64,
 ) -> bool {
-    (*bson).append(key, val)
+    catch_panic(std::ptr::null_mut(), || (*bson).append(key, val))
 }
 
 #[no_mangle]
@@ -99,8 +183,10 @@ pub unsafe extern "C" fn bson_append_utf8(
     val: *const c_char,
     _length: isize,
 ) -> bool {
-    let s = CStr::from_ptr(val).to_string_lossy();
-    (*bson).append(key, s.to_string())
+    catch_panic(std::ptr::null_mut(), || {
+        let s = CStr::from_ptr(val).to_string_lossy();
+        (*bson).append(key, s.to_string())
+    })
 }
 
 #[no_mangle]
@@ -110,7 +196,9 @@ pub unsafe extern "C" fn bson_append_document(
     _key_length: isize,
     val: *const bson_t,
 ) -> bool {
-    (*bson).append(key, (*val).deref().to_raw_document_buf())
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, (*val).deref().to_raw_document_buf())
+    })
 }
 
 #[no_mangle]
@@ -120,10 +208,12 @@ pub unsafe extern "C" fn bson_append_array(
     _key_length: isize,
     val: *const bson_t,
 ) -> bool {
-    (*bson).append(
-        key,
-        RawArrayBuf::from_raw_document_buf((*val).doc.clone().into_owned()),
-    )
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(
+            key,
+            RawArrayBuf::from_raw_document_buf((*val).doc.clone().into_owned()),
+        )
+    })
 }
 
 #[no_mangle]
@@ -135,12 +225,14 @@ pub unsafe extern "C" fn bson_append_binary(
     val: *const u8,
     length: u32,
 ) -> bool {
-    let slice = std::slice::from_raw_parts(val, length as usize);
-    let binary = RawBinaryRef {
-        bytes: slice,
-        subtype: (subtype as u8).into(),
-    };
-    (*bson).append(key, RawBsonRef::Binary(binary))
+    catch_panic(std::ptr::null_mut(), || {
+        let slice = std::slice::from_raw_parts(val, length as usize);
+        let binary = RawBinaryRef {
+            bytes: slice,
+            subtype: (subtype as u8).into(),
+        };
+        (*bson).append(key, RawBsonRef::Binary(binary))
+    })
 }
 
 #[no_mangle]
@@ -150,7 +242,7 @@ pub unsafe extern "C" fn bson_append_bool(
     _key_length: isize,
     val: bool,
 ) -> bool {
-    (*bson).append(key, val)
+    catch_panic(std::ptr::null_mut(), || (*bson).append(key, val))
 }
 
 #[no_mangle]
@@ -160,8 +252,10 @@ pub unsafe extern "C" fn bson_append_code(
     _key_length: isize,
     val: *const c_char,
 ) -> bool {
-    let s = CStr::from_ptr(val).to_string_lossy();
-    (*bson).append(key, RawBson::JavaScriptCode(s.to_string()))
+    catch_panic(std::ptr::null_mut(), || {
+        let s = CStr::from_ptr(val).to_string_lossy();
+        (*bson).append(key, RawBson::JavaScriptCode(s.to_string()))
+    })
 }
 
 #[no_mangle]
@@ -172,15 +266,17 @@ pub unsafe extern "C" fn bson_append_code_with_scope(
     code: *const c_char,
     scope: *const bson_t,
 ) -> bool {
-    let s = CStr::from_ptr(code).to_string_lossy();
-    let scope = (*scope).doc.clone();
-    (*bson).append(
-        key,
-        RawJavaScriptCodeWithScope {
-            code: s.to_string(),
-            scope: scope.into_owned(),
-        },
-    )
+    catch_panic(std::ptr::null_mut(), || {
+        let s = CStr::from_ptr(code).to_string_lossy();
+        let scope = (*scope).doc.clone();
+        (*bson).append(
+            key,
+            RawJavaScriptCodeWithScope {
+                code: s.to_string(),
+                scope: scope.into_owned(),
+            },
+        )
+    })
 }
 
 #[no_mangle]
@@ -190,8 +286,10 @@ pub unsafe extern "C" fn bson_append_date_time(
     _key_length: isize,
     value: i64,
 ) -> bool {
-    let dt = DateTime::from_millis(value);
-    (*bson).append(key, dt)
+    catch_panic(std::ptr::null_mut(), || {
+        let dt = DateTime::from_millis(value);
+        (*bson).append(key, dt)
+    })
 }
 
 #[no_mangle]
@@ -202,13 +300,15 @@ pub unsafe extern "C" fn bson_append_dbpointer(
     collection: *const c_char,
     oid: *const bson_oid_t,
 ) -> bool {
-    (*bson).append(
-        key,
-        DbPointer {
-            id: ObjectId::from_bytes((*oid).bytes),
-            namespace: CStr::from_ptr(collection).to_string_lossy().to_string(),
-        },
-    )
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(
+            key,
+            DbPointer {
+                id: ObjectId::from_bytes((*oid).bytes),
+                namespace: CStr::from_ptr(collection).to_string_lossy().to_string(),
+            },
+        )
+    })
 }
 
 #[no_mangle]
@@ -218,14 +318,16 @@ pub unsafe extern "C" fn bson_append_decimal128(
     _key_length: isize,
     val: *const bson_decimal128_t,
 ) -> bool {
-    let low = (*val).low.to_le_bytes();
-    let high = (*val).high.to_le_bytes();
+    catch_panic(std::ptr::null_mut(), || {
+        let low = (*val).low.to_le_bytes();
+        let high = (*val).high.to_le_bytes();
 
-    let bytes = [
-        low[0], low[1], low[2], low[3], low[4], low[5], low[6], low[7], high[0], high[1], high[2],
-        high[3], high[4], high[5], high[6], high[7],
-    ];
-    (*bson).append(key, Decimal128::from_bytes(bytes))
+        let bytes = [
+            low[0], low[1], low[2], low[3], low[4], low[5], low[6], low[7], high[0], high[1],
+            high[2], high[3], high[4], high[5], high[6], high[7],
+        ];
+        (*bson).append(key, Decimal128::from_bytes(bytes))
+    })
 }
 
 #[no_mangle]
@@ -235,7 +337,7 @@ pub unsafe extern "C" fn bson_append_double(
     _key_length: isize,
     val: f64,
 ) -> bool {
-    (*bson).append(key, val)
+    catch_panic(std::ptr::null_mut(), || (*bson).append(key, val))
 }
 
 #[no_mangle]
@@ -244,7 +346,9 @@ pub unsafe extern "C" fn bson_append_maxkey(
     key: *const c_char,
     _key_length: isize,
 ) -> bool {
-    (*bson).append(key, RawBson::MaxKey)
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, RawBson::MaxKey)
+    })
 }
 
 #[no_mangle]
@@ -253,7 +357,9 @@ pub unsafe extern "C" fn bson_append_minkey(
     key: *const c_char,
     _key_length: isize,
 ) -> bool {
-    (*bson).append(key, RawBson::MinKey)
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, RawBson::MinKey)
+    })
 }
 
 #[no_mangle]
@@ -262,7 +368,9 @@ pub unsafe extern "C" fn bson_append_now_utc(
     key: *const c_char,
     _key_length: isize,
 ) -> bool {
-    (*bson).append(key, DateTime::now())
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, DateTime::now())
+    })
 }
 
 #[no_mangle]
@@ -271,7 +379,7 @@ pub unsafe extern "C" fn bson_append_null(
     key: *const c_char,
     _key_length: isize,
 ) -> bool {
-    (*bson).append(key, RawBson::Null)
+    catch_panic(std::ptr::null_mut(), || (*bson).append(key, RawBson::Null))
 }
 
 #[no_mangle]
@@ -281,7 +389,9 @@ pub unsafe extern "C" fn bson_append_oid(
     _key_length: isize,
     oid: *const bson_oid_t,
 ) -> bool {
-    (*bson).append(key, ObjectId::from_bytes((*oid).bytes))
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, ObjectId::from_bytes((*oid).bytes))
+    })
 }
 
 #[no_mangle]
@@ -292,15 +402,17 @@ pub unsafe extern "C" fn bson_append_regex(
     regex: *const c_char,
     options: *const c_char,
 ) -> bool {
-    let regex = CStr::from_ptr(regex).to_string_lossy();
-    let options = CStr::from_ptr(options).to_string_lossy();
-    (*bson).append(
-        key,
-        RawBsonRef::RegularExpression(RawRegexRef {
-            pattern: regex.as_ref(),
-            options: options.as_ref(),
-        }),
-    )
+    catch_panic(std::ptr::null_mut(), || {
+        let regex = CStr::from_ptr(regex).to_string_lossy();
+        let options = CStr::from_ptr(options).to_string_lossy();
+        (*bson).append(
+            key,
+            RawBsonRef::RegularExpression(RawRegexRef {
+                pattern: regex.as_ref(),
+                options: options.as_ref(),
+            }),
+        )
+    })
 }
 
 #[no_mangle]
@@ -311,8 +423,10 @@ pub unsafe extern "C" fn bson_append_symbol(
     value: *const c_char,
     _length: isize,
 ) -> bool {
-    let s = CStr::from_ptr(value).to_string_lossy();
-    (*bson).append(key, RawBsonRef::Symbol(s.as_ref()))
+    catch_panic(std::ptr::null_mut(), || {
+        let s = CStr::from_ptr(value).to_string_lossy();
+        (*bson).append(key, RawBsonRef::Symbol(s.as_ref()))
+    })
 }
 
 #[no_mangle]
@@ -323,7 +437,9 @@ pub unsafe extern "C" fn bson_append_timestamp(
     time: u32,
     increment: u32,
 ) -> bool {
-    (*bson).append(key, Timestamp { time, increment })
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, Timestamp { time, increment })
+    })
 }
 
 #[no_mangle]
@@ -332,29 +448,35 @@ pub unsafe extern "C" fn bson_append_undefined(
     key: *const c_char,
     _key_length: isize,
 ) -> bool {
-    (*bson).append(key, RawBson::Undefined)
+    catch_panic(std::ptr::null_mut(), || {
+        (*bson).append(key, RawBson::Undefined)
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn bson_to_string(bson: *const bson_t) -> *mut c_char {
-    CString::new(format!("{}", (*bson).to_document().unwrap()))
-        .unwrap()
-        .into_raw()
+    catch_panic(std::ptr::null_mut(), || {
+        CString::new(format!("{}", (*bson).to_document().unwrap()))
+            .unwrap()
+            .into_raw()
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn bson_get_data(bson: *const bson_t) -> *const u8 {
-    (*bson).as_bytes().as_ptr()
+    catch_panic(std::ptr::null_mut(), || (*bson).as_bytes().as_ptr())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn bson_len(bson: *const bson_t) -> usize {
-    (*bson).as_bytes().len()
+    catch_panic(std::ptr::null_mut(), || (*bson).as_bytes().len())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn bson_destroy(bson: *mut bson_t) {
-    drop(Box::from_raw(bson));
+    catch_panic(std::ptr::null_mut(), || {
+        drop(Box::from_raw(bson));
+    })
 }
 
 #[allow(non_camel_case_types)]
@@ -371,28 +493,224 @@ pub enum bson_subtype_t {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct bson_oid_t {
     bytes: [u8; 12],
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn bson_oid_to_string(bson: *const bson_oid_t, out: *mut c_char) {
-    let oid = ObjectId::from_bytes((*bson).bytes);
-    let s = CString::new(oid.to_hex()).unwrap();
+    catch_panic(std::ptr::null_mut(), || {
+        let oid = ObjectId::from_bytes((*bson).bytes);
+        let s = CString::new(oid.to_hex()).unwrap();
 
-    let out_bytes = std::slice::from_raw_parts_mut(out as *mut u8, 25);
-    out_bytes[..].copy_from_slice(s.as_bytes_with_nul());
+        let out_bytes = std::slice::from_raw_parts_mut(out as *mut u8, 25);
+        out_bytes[..].copy_from_slice(s.as_bytes_with_nul());
+    })
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct bson_decimal128_t {
     low: u64,
     high: u64,
 }
 
+impl bson_decimal128_t {
+    fn to_decimal128(&self) -> Decimal128 {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.low.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.high.to_le_bytes());
+        Decimal128::from_bytes(bytes)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum bson_type_t {
+    BSON_TYPE_EOD = 0x00,
+    BSON_TYPE_DOUBLE = 0x01,
+    BSON_TYPE_UTF8 = 0x02,
+    BSON_TYPE_DOCUMENT = 0x03,
+    BSON_TYPE_ARRAY = 0x04,
+    BSON_TYPE_BINARY = 0x05,
+    BSON_TYPE_UNDEFINED = 0x06,
+    BSON_TYPE_OID = 0x07,
+    BSON_TYPE_BOOL = 0x08,
+    BSON_TYPE_DATE_TIME = 0x09,
+    BSON_TYPE_NULL = 0x0A,
+    BSON_TYPE_REGEX = 0x0B,
+    BSON_TYPE_DBPOINTER = 0x0C,
+    BSON_TYPE_CODE = 0x0D,
+    BSON_TYPE_SYMBOL = 0x0E,
+    BSON_TYPE_CODEWSCOPE = 0x0F,
+    BSON_TYPE_INT32 = 0x10,
+    BSON_TYPE_TIMESTAMP = 0x11,
+    BSON_TYPE_INT64 = 0x12,
+    BSON_TYPE_DECIMAL128 = 0x13,
+    BSON_TYPE_MAXKEY = 0x7F,
+    BSON_TYPE_MINKEY = 0xFF,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_timestamp_t {
+    timestamp: u32,
+    increment: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_string_t {
+    str: *mut c_char,
+    len: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_doc_t {
+    data: *mut u8,
+    data_len: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_binary_t {
+    data: *mut u8,
+    data_len: u32,
+    subtype: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_regex_t {
+    regex: *mut c_char,
+    options: *mut c_char,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_dbpointer_t {
+    collection: *mut c_char,
+    collection_len: u32,
+    oid: bson_oid_t,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct bson_value_codewscope_t {
+    code: *mut c_char,
+    scope_data: *mut u8,
+    code_len: u32,
+    scope_len: u32,
+}
+
+/// The value part of bson_value_t. The field names match libbson's so C code can access them.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union bson_value_value_t {
+    v_oid: bson_oid_t,
+    v_int64: i64,
+    v_int32: i32,
+    v_int8: i8,
+    v_double: f64,
+    v_bool: bool,
+    v_datetime: i64,
+    v_timestamp: bson_value_timestamp_t,
+    v_utf8: bson_value_string_t,
+    v_doc: bson_value_doc_t,
+    v_binary: bson_value_binary_t,
+    v_regex: bson_value_regex_t,
+    v_dbpointer: bson_value_dbpointer_t,
+    v_code: bson_value_string_t,
+    v_codewscope: bson_value_codewscope_t,
+    v_symbol: bson_value_string_t,
+    v_decimal128: bson_decimal128_t,
+}
+
+/// A single BSON value, laid out the same way as libbson's bson_value_t.
+#[repr(C)]
+pub struct bson_value_t {
+    value_type: bson_type_t,
+    padding: i32,
+    value: bson_value_value_t,
+}
+
+impl bson_value_t {
+    /// Copies the value into an owned Rust value.
+    pub(crate) unsafe fn to_raw_bson(&self) -> Result<RawBson> {
+        unsafe fn string(ptr: *const c_char, len: u32) -> Result<String> {
+            let bytes = std::slice::from_raw_parts(ptr as *const u8, len as usize);
+            Ok(std::str::from_utf8(bytes)?.to_string())
+        }
+        unsafe fn document(ptr: *const u8, len: u32) -> Result<RawDocumentBuf> {
+            let bytes = std::slice::from_raw_parts(ptr, len as usize);
+            Ok(RawDocument::from_bytes(bytes)?.to_raw_document_buf())
+        }
+
+        let v = &self.value;
+        let value = match self.value_type {
+            bson_type_t::BSON_TYPE_DOUBLE => RawBson::Double(v.v_double),
+            bson_type_t::BSON_TYPE_UTF8 => RawBson::String(string(v.v_utf8.str, v.v_utf8.len)?),
+            bson_type_t::BSON_TYPE_DOCUMENT => {
+                RawBson::Document(document(v.v_doc.data, v.v_doc.data_len)?)
+            }
+            bson_type_t::BSON_TYPE_ARRAY => RawBson::Array(RawArrayBuf::from_raw_document_buf(
+                document(v.v_doc.data, v.v_doc.data_len)?,
+            )),
+            bson_type_t::BSON_TYPE_BINARY => RawBson::Binary(mongodb::bson::Binary {
+                subtype: (v.v_binary.subtype as u8).into(),
+                bytes: std::slice::from_raw_parts(v.v_binary.data, v.v_binary.data_len as usize)
+                    .to_vec(),
+            }),
+            bson_type_t::BSON_TYPE_UNDEFINED => RawBson::Undefined,
+            bson_type_t::BSON_TYPE_OID => RawBson::ObjectId(ObjectId::from_bytes(v.v_oid.bytes)),
+            bson_type_t::BSON_TYPE_BOOL => RawBson::Boolean(v.v_bool),
+            bson_type_t::BSON_TYPE_DATE_TIME => {
+                RawBson::DateTime(DateTime::from_millis(v.v_datetime))
+            }
+            bson_type_t::BSON_TYPE_NULL => RawBson::Null,
+            bson_type_t::BSON_TYPE_REGEX => RawBson::RegularExpression(mongodb::bson::Regex {
+                pattern: CStr::from_ptr(v.v_regex.regex).to_str()?.to_string(),
+                options: CStr::from_ptr(v.v_regex.options).to_str()?.to_string(),
+            }),
+            bson_type_t::BSON_TYPE_DBPOINTER => RawBson::DbPointer(DbPointer {
+                namespace: string(v.v_dbpointer.collection, v.v_dbpointer.collection_len)?,
+                id: ObjectId::from_bytes(v.v_dbpointer.oid.bytes),
+            }),
+            bson_type_t::BSON_TYPE_CODE => {
+                RawBson::JavaScriptCode(string(v.v_code.str, v.v_code.len)?)
+            }
+            bson_type_t::BSON_TYPE_SYMBOL => {
+                RawBson::Symbol(string(v.v_symbol.str, v.v_symbol.len)?)
+            }
+            bson_type_t::BSON_TYPE_CODEWSCOPE => {
+                RawBson::JavaScriptCodeWithScope(RawJavaScriptCodeWithScope {
+                    code: string(v.v_codewscope.code, v.v_codewscope.code_len)?,
+                    scope: document(v.v_codewscope.scope_data, v.v_codewscope.scope_len)?,
+                })
+            }
+            bson_type_t::BSON_TYPE_INT32 => RawBson::Int32(v.v_int32),
+            bson_type_t::BSON_TYPE_TIMESTAMP => RawBson::Timestamp(Timestamp {
+                time: v.v_timestamp.timestamp,
+                increment: v.v_timestamp.increment,
+            }),
+            bson_type_t::BSON_TYPE_INT64 => RawBson::Int64(v.v_int64),
+            bson_type_t::BSON_TYPE_DECIMAL128 => {
+                RawBson::Decimal128(v.v_decimal128.to_decimal128())
+            }
+            bson_type_t::BSON_TYPE_MAXKEY => RawBson::MaxKey,
+            bson_type_t::BSON_TYPE_MINKEY => RawBson::MinKey,
+            bson_type_t::BSON_TYPE_EOD => anyhow::bail!("invalid bson value type"),
+        };
+        Ok(value)
+    }
+}
+
 #[repr(C)]
 pub struct bson_error_t {
-    domain: u32,
-    code: u32,
-    message: [c_char; 504],
+    pub(crate) domain: u32,
+    pub(crate) code: u32,
+    pub(crate) message: [c_char; 504],
 }
@@ -1,123 +1,846 @@
+use std::{
+    ffi::CStr,
+    os::raw::c_char,
+    sync::{Arc, Mutex},
+};
+
 use mongodb::{
-    bson::{rawdoc, Document, RawDocumentBuf},
-    options::{DeleteOptions, InsertManyOptions, InsertOneOptions, ReplaceOptions, UpdateOptions},
-    sync::Collection,
+    bson::{
+        doc, oid::ObjectId, rawdoc, Bson, Document, RawArrayBuf, RawBsonRef, RawDocument,
+        RawDocumentBuf,
+    },
+    options::{SelectionCriteria, WriteConcern},
+    sync::Client,
+    ServerInfo, ServerType,
 };
 
 use crate::{
-    bson::{bson_error_t, bson_t},
+    bson::{self, bson_error_t, bson_t, bson_validate_flags_t, bson_value_t},
+    client::{self, mongoc_client_t},
+    collection::mongoc_collection_t,
+    error::{catch_panic, mongoc_error_domain_t, set_error, MongocError},
+    session::mongoc_client_session_t,
     write_concern::mongoc_write_concern_t,
+    MONGOC_ERROR_API_VERSION_2, MONGOC_ERROR_API_VERSION_LEGACY,
 };
 
+/// The server's limit on the number of operations in a single write command.
+const MAX_WRITE_BATCH_SIZE: usize = 100_000;
+
+/// The server's limit on the size of a command document. Batches are split well before this to
+/// leave room for the rest of the command.
+const MAX_BATCH_BYTES: usize = 16 * 1024 * 1024 - 16 * 1024;
+
 pub struct mongoc_bulk_operation_t {
-    operation: Operation,
-    collection: Collection<RawDocumentBuf>,
+    operations: Vec<WriteModel>,
+    ordered: bool,
+    executed: bool,
+    /// An error from creating or building the bulk, reported when it is executed.
+    error: Option<anyhow::Error>,
+    client: Option<Client>,
+    database: Option<String>,
+    collection: Option<String>,
+    write_concern: mongoc_write_concern_t,
+    bypass_document_validation: Option<bool>,
+    session: *mut mongoc_client_session_t,
+    comment: Option<Bson>,
+    let_vars: Option<Document>,
+    /// The server id set with mongoc_bulk_operation_set_hint, or 0 if none was set, in which case
+    /// the bulk runs on the primary.
+    server_id: u32,
+    error_api: i32,
 }
 
 impl mongoc_bulk_operation_t {
-    pub(crate) fn new(collection: Collection<RawDocumentBuf>) -> Self {
+    fn new(ordered: bool) -> Self {
+        Self {
+            operations: Vec::new(),
+            ordered,
+            executed: false,
+            error: None,
+            client: None,
+            database: None,
+            collection: None,
+            write_concern: WriteConcern::default().into(),
+            bypass_document_validation: None,
+            session: std::ptr::null_mut(),
+            comment: None,
+            let_vars: None,
+            server_id: 0,
+            error_api: MONGOC_ERROR_API_VERSION_LEGACY,
+        }
+    }
+
+    /// Creates a bulk that writes to `collection`, inheriting its write concern.
+    pub(crate) fn for_collection(collection: &mongoc_collection_t) -> Self {
+        let namespace = collection.namespace();
         Self {
-            operation: Operation::None,
-            collection,
+            client: Some(collection.client.clone()),
+            database: Some(namespace.db),
+            collection: Some(namespace.coll),
+            write_concern: collection.write_concern.clone(),
+            error_api: collection.error_api,
+            ..Self::new(true)
         }
     }
+
+    /// Applies the options accepted by mongoc_collection_create_bulk_operation_with_opts, which
+    /// set the same fields as the mongoc_bulk_operation_set_* functions.
+    pub(crate) unsafe fn apply_opts(&mut self, opts: &RawDocument) {
+        let result: anyhow::Result<_> = (|| {
+            for kvp in opts {
+                match kvp? {
+                    ("ordered", RawBsonRef::Boolean(ordered)) => self.ordered = ordered,
+                    ("writeConcern", RawBsonRef::Document(wc)) => {
+                        let wc: WriteConcern =
+                            mongodb::bson::from_document(Document::try_from(wc)?)?;
+                        self.write_concern = wc.into();
+                    }
+                    ("sessionId", _) => {
+                        self.session = mongoc_client_session_t::from_opts(&bson_t::from(opts))?
+                    }
+                    ("bypassDocumentValidation", RawBsonRef::Boolean(bypass)) => {
+                        self.bypass_document_validation = Some(bypass)
+                    }
+                    ("comment", comment) => {
+                        self.comment = Some(Bson::try_from(comment.to_raw_bson())?)
+                    }
+                    ("let", RawBsonRef::Document(let_vars)) => {
+                        self.let_vars = Some(Document::try_from(let_vars)?)
+                    }
+                    (
+                        key @ ("ordered" | "writeConcern" | "bypassDocumentValidation" | "let"),
+                        other,
+                    ) => {
+                        anyhow::bail!("Invalid field \"{}\" in opts, got {:?}", key, other)
+                    }
+                    (key, _) => anyhow::bail!("Invalid option '{}'", key),
+                }
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    unsafe fn execute(&mut self) -> anyhow::Result<BulkResult> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.executed {
+            anyhow::bail!("Cannot call execute() twice on a bulk operation");
+        }
+        self.executed = true;
+        if self.operations.is_empty() {
+            anyhow::bail!("Cannot do an empty bulk write");
+        }
+        if !self.write_concern.is_valid() {
+            anyhow::bail!("The write concern is invalid.");
+        }
+
+        let (client, database, collection) = match (&self.client, &self.database, &self.collection)
+        {
+            (None, _, _) => anyhow::bail!(
+                "mongoc_bulk_operation_execute() requires a client and one has not been set."
+            ),
+            (_, None, _) => anyhow::bail!(
+                "mongoc_bulk_operation_execute() requires a database and one has not been \
+                     set."
+            ),
+            (_, _, None) => anyhow::bail!(
+                "mongoc_bulk_operation_execute() requires a collection and one has not been \
+                     set."
+            ),
+            (Some(client), Some(database), Some(collection)) => (client, database, collection),
+        };
+        let database = client.database(database);
+        // As in libmongoc, no write concern is sent for the operations of a transaction.
+        let in_transaction = self.session.as_ref().map_or(false, |s| s.in_transaction);
+        let write_concern = if in_transaction {
+            Document::new()
+        } else {
+            mongodb::bson::to_document(&*self.write_concern)?
+        };
+
+        let mut result = BulkResult::default();
+        let selected = Arc::new(Mutex::new(None));
+        for batch in self.batches() {
+            let (kind, offset) = (batch.kind, batch.offset);
+            let reply = batch
+                .into_command(collection, self.ordered)
+                .and_then(|mut command| {
+                    if !write_concern.is_empty() {
+                        command.insert("writeConcern", write_concern.clone());
+                    }
+                    if let Some(bypass) = self.bypass_document_validation {
+                        if kind != CommandKind::Delete {
+                            command.insert("bypassDocumentValidation", bypass);
+                        }
+                    }
+                    if let Some(ref comment) = self.comment {
+                        command.insert("comment", comment.clone());
+                    }
+                    if let Some(ref let_vars) = self.let_vars {
+                        if kind != CommandKind::Insert {
+                            command.insert("let", let_vars.clone());
+                        }
+                    }
+
+                    let selection_criteria = self.selection_criteria(&selected);
+                    let reply = match self.session.as_mut() {
+                        Some(session) => database.run_command_with_session(
+                            command,
+                            selection_criteria,
+                            session,
+                        )?,
+                        None => database.run_command(command, selection_criteria)?,
+                    };
+                    Ok(reply)
+                });
+
+            match reply {
+                Ok(reply) => {
+                    // Nothing was selected if the driver bypassed the selection criteria, as it
+                    // does for a session pinned to a mongos in a transaction.
+                    result.server_id = selected
+                        .lock()
+                        .unwrap()
+                        .take()
+                        .unwrap_or(client::UNKNOWN_SERVER_ID);
+                    let had_write_errors = result.merge(kind, &reply, offset);
+                    if had_write_errors && self.ordered {
+                        break;
+                    }
+                }
+                // A command that failed outright ends the bulk regardless of ordering, but the
+                // results of the batches that already ran are still reported.
+                Err(e) => {
+                    result.error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Selects the server named by the hint, or else a single writable server, and records the
+    /// id of the server chosen in `selected`.
+    fn selection_criteria(&self, selected: &Arc<Mutex<Option<u32>>>) -> SelectionCriteria {
+        let hint = self.server_id;
+        let selected = Arc::clone(selected);
+        SelectionCriteria::Predicate(Arc::new(move |server: &ServerInfo| {
+            let id = client::server_id(server.address());
+            let mut selected = selected.lock().unwrap();
+            let suitable = match hint {
+                // Only the first writable server is accepted, so the driver can't pick another
+                // one among several mongoses and the server reported is the one used.
+                0 => {
+                    selected.map_or(true, |s| s == id)
+                        && matches!(
+                            server.server_type(),
+                            ServerType::Standalone
+                                | ServerType::RsPrimary
+                                | ServerType::Mongos
+                                | ServerType::LoadBalancer
+                        )
+                }
+                hint => hint == id,
+            };
+            if suitable {
+                *selected = Some(id);
+            }
+            suitable
+        }))
+    }
+
+    /// Groups contiguous operations of the same command type into batches that fit within the
+    /// server's limits.
+    fn batches(&self) -> Vec<Batch<'_>> {
+        let mut batches: Vec<Batch> = Vec::new();
+
+        for (index, op) in self.operations.iter().enumerate() {
+            let size = op.size();
+            match batches.last_mut() {
+                Some(batch)
+                    if batch.kind == op.kind()
+                        && batch.operations.len() < MAX_WRITE_BATCH_SIZE
+                        && batch.size + size <= MAX_BATCH_BYTES =>
+                {
+                    batch.operations.push(op);
+                    batch.size += size;
+                }
+                _ => batches.push(Batch {
+                    kind: op.kind(),
+                    offset: index,
+                    size,
+                    operations: vec![op],
+                }),
+            }
+        }
+
+        batches
+    }
+
+    unsafe fn push(
+        &mut self,
+        error: *mut bson_error_t,
+        op: impl FnOnce() -> anyhow::Result<WriteModel>,
+    ) -> bool {
+        match op() {
+            Ok(op) => {
+                self.operations.push(op);
+                true
+            }
+            Err(e) => {
+                set_error(error, self.error_api, &e);
+                false
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CommandKind {
+    Insert,
+    Update,
+    Delete,
 }
 
 #[derive(Debug)]
-enum Operation {
-    None,
+enum WriteModel {
+    InsertOne {
+        document: RawDocumentBuf,
+    },
     UpdateOne {
         filter: Document,
-        update: Document,
-        options: Option<UpdateOptions>,
+        update: Bson,
+        options: Document,
     },
     UpdateMany {
         filter: Document,
-        update: Document,
-        options: Option<UpdateOptions>,
+        update: Bson,
+        options: Document,
     },
     ReplaceOne {
         filter: Document,
         replacement: RawDocumentBuf,
-        options: Option<ReplaceOptions>,
-    },
-    InsertOne {
-        document: RawDocumentBuf,
-        options: Option<InsertOneOptions>,
-    },
-    InsertMany {
-        documents: Vec<RawDocumentBuf>,
-        options: Option<InsertManyOptions>,
+        options: Document,
     },
     DeleteOne {
         filter: Document,
-        options: Option<DeleteOptions>,
+        options: Document,
     },
     DeleteMany {
         filter: Document,
-        options: Option<DeleteOptions>,
+        options: Document,
     },
 }
 
+impl WriteModel {
+    fn kind(&self) -> CommandKind {
+        match self {
+            WriteModel::InsertOne { .. } => CommandKind::Insert,
+            WriteModel::UpdateOne { .. }
+            | WriteModel::UpdateMany { .. }
+            | WriteModel::ReplaceOne { .. } => CommandKind::Update,
+            WriteModel::DeleteOne { .. } | WriteModel::DeleteMany { .. } => CommandKind::Delete,
+        }
+    }
+
+    /// A rough size of this operation once it's in a command, used for splitting batches.
+    fn size(&self) -> usize {
+        let doc_size = |d: &Document| mongodb::bson::to_vec(d).map(|v| v.len()).unwrap_or(0);
+        match self {
+            WriteModel::InsertOne { document } => document.as_bytes().len(),
+            WriteModel::UpdateOne {
+                filter,
+                update,
+                options,
+            }
+            | WriteModel::UpdateMany {
+                filter,
+                update,
+                options,
+            } => doc_size(filter) + doc_size(&doc! { "u": update.clone() }) + doc_size(options),
+            WriteModel::ReplaceOne {
+                filter,
+                replacement,
+                options,
+            } => doc_size(filter) + replacement.as_bytes().len() + doc_size(options),
+            WriteModel::DeleteOne { filter, options }
+            | WriteModel::DeleteMany { filter, options } => doc_size(filter) + doc_size(options),
+        }
+    }
+
+    /// The entry for this operation in the documents/updates/deletes array of its command.
+    fn to_command_entry(&self) -> anyhow::Result<Bson> {
+        let (mut entry, options) = match self {
+            WriteModel::InsertOne { document } => {
+                return Ok(Bson::Document(document.as_ref().try_into()?))
+            }
+            WriteModel::UpdateOne {
+                filter,
+                update,
+                options,
+            } => (
+                doc! {
+                    "q": filter.clone(),
+                    "u": update.clone(),
+                    "multi": false,
+                },
+                options,
+            ),
+            WriteModel::UpdateMany {
+                filter,
+                update,
+                options,
+            } => (
+                doc! {
+                    "q": filter.clone(),
+                    "u": update.clone(),
+                    "multi": true,
+                },
+                options,
+            ),
+            WriteModel::ReplaceOne {
+                filter,
+                replacement,
+                options,
+            } => {
+                let replacement: Document = replacement.as_ref().try_into()?;
+                (
+                    doc! {
+                        "q": filter.clone(),
+                        "u": replacement,
+                        "multi": false,
+                    },
+                    options,
+                )
+            }
+            WriteModel::DeleteOne { filter, options } => (
+                doc! {
+                    "q": filter.clone(),
+                    "limit": 1,
+                },
+                options,
+            ),
+            WriteModel::DeleteMany { filter, options } => (
+                doc! {
+                    "q": filter.clone(),
+                    "limit": 0,
+                },
+                options,
+            ),
+        };
+        entry.extend(options.clone());
+        Ok(Bson::Document(entry))
+    }
+}
+
+/// The options accepted by the mongoc_bulk_operation_*_with_opts functions.
+#[derive(Debug, Default)]
+struct OperationOpts {
+    validate: Option<bson_validate_flags_t>,
+    limit: Option<i32>,
+    /// Options that are sent as-is in the operation's entry of the write command.
+    entry: Document,
+}
+
+impl OperationOpts {
+    /// Parses `opts`, rejecting any option not in `allowed` as libmongoc does.
+    unsafe fn parse(opts: *const bson_t, allowed: &[&str]) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        if opts.is_null() {
+            return Ok(parsed);
+        }
+
+        for kvp in (*opts).into_iter() {
+            let (key, value) = kvp?;
+            if !allowed.contains(&key) {
+                anyhow::bail!("Invalid option '{}'", key);
+            }
+
+            match (key, value) {
+                ("validate", RawBsonRef::Boolean(false)) => {
+                    parsed.validate = Some(bson_validate_flags_t::BSON_VALIDATE_NONE)
+                }
+                ("validate", RawBsonRef::Boolean(true)) => anyhow::bail!(
+                    "Invalid option \"validate\": true, must be a bitwise-OR of \
+                     bson_validate_flags_t values."
+                ),
+                ("validate", RawBsonRef::Int32(flags)) => {
+                    match u32::try_from(flags)
+                        .ok()
+                        .and_then(bson_validate_flags_t::from_bits)
+                    {
+                        Some(flags) => parsed.validate = Some(flags),
+                        None => anyhow::bail!(
+                            "Invalid field \"validate\" in opts, must be a bitwise-OR of \
+                             bson_validate_flags_t values."
+                        ),
+                    }
+                }
+                ("limit", RawBsonRef::Int32(limit)) => parsed.limit = Some(limit),
+                ("upsert", RawBsonRef::Boolean(_))
+                | ("collation", RawBsonRef::Document(_))
+                | ("arrayFilters", RawBsonRef::Array(_))
+                | ("hint", RawBsonRef::Document(_) | RawBsonRef::String(_)) => {
+                    parsed
+                        .entry
+                        .insert(key, Bson::try_from(value.to_raw_bson())?);
+                }
+                (key, value) => anyhow::bail!(
+                    "Invalid type for option \"{}\": {:?}",
+                    key,
+                    value.element_type()
+                ),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Checks that a "limit" option, if given, matches the kind of delete it was passed to.
+    fn check_limit(&self, expected: i32) -> anyhow::Result<()> {
+        match self.limit {
+            Some(limit) if limit != expected => anyhow::bail!(
+                "Invalid \"limit\" in opts: {}. The value must be {}, or omitted.",
+                limit,
+                expected
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Batch<'a> {
+    kind: CommandKind,
+    /// The index of the first operation of this batch in the bulk.
+    offset: usize,
+    size: usize,
+    operations: Vec<&'a WriteModel>,
+}
+
+impl<'a> Batch<'a> {
+    fn into_command(self, collection: &str, ordered: bool) -> anyhow::Result<Document> {
+        let entries = self
+            .operations
+            .iter()
+            .map(|op| op.to_command_entry())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut command = match self.kind {
+            CommandKind::Insert => doc! { "insert": collection, "documents": entries },
+            CommandKind::Update => doc! { "update": collection, "updates": entries },
+            CommandKind::Delete => doc! { "delete": collection, "deletes": entries },
+        };
+        command.insert("ordered", ordered);
+        Ok(command)
+    }
+}
+
+/// The combined results of every command run by a bulk.
+#[derive(Debug, Default)]
+struct BulkResult {
+    n_inserted: i32,
+    n_matched: i32,
+    n_modified: i32,
+    n_removed: i32,
+    n_upserted: i32,
+    upserted: Vec<Document>,
+    write_errors: Vec<Document>,
+    write_concern_errors: Vec<Document>,
+    /// An error that prevented one of the commands from running at all.
+    error: Option<anyhow::Error>,
+    /// The id of the server the last command ran on, 0 if none ran, or UNKNOWN_SERVER_ID if one
+    /// ran on a server that couldn't be identified.
+    server_id: u32,
+}
+
+impl BulkResult {
+    /// Folds the reply of a command of the given kind into the result. Returns whether the
+    /// command reported write errors.
+    fn merge(&mut self, kind: CommandKind, reply: &Document, offset: usize) -> bool {
+        // Indexes in the reply are relative to the batch, not the whole bulk.
+        let remap_index = |doc: &Document| get_i64(doc, "index") as i32 + offset as i32;
+
+        let n = get_i64(reply, "n") as i32;
+        match kind {
+            CommandKind::Insert => self.n_inserted += n,
+            CommandKind::Update => {
+                let mut n_upserted = 0;
+                for upserted in documents(reply, "upserted") {
+                    self.upserted.push(doc! {
+                        "index": remap_index(upserted),
+                        "_id": upserted.get("_id").cloned().unwrap_or(Bson::Null),
+                    });
+                    n_upserted += 1;
+                }
+                self.n_matched += n - n_upserted;
+                self.n_modified += get_i64(reply, "nModified") as i32;
+                self.n_upserted += n_upserted;
+            }
+            CommandKind::Delete => self.n_removed += n,
+        }
+
+        let mut had_write_errors = false;
+        for error in documents(reply, "writeErrors") {
+            let mut write_error = doc! {
+                "index": remap_index(error),
+                "code": get_i64(error, "code") as i32,
+                "errmsg": error.get_str("errmsg").unwrap_or_default(),
+            };
+            if let Some(info) = error.get("errInfo") {
+                write_error.insert("errInfo", info.clone());
+            }
+            self.write_errors.push(write_error);
+            had_write_errors = true;
+        }
+
+        if let Ok(error) = reply.get_document("writeConcernError") {
+            let mut write_concern_error = doc! {
+                "code": get_i64(error, "code") as i32,
+                "errmsg": error.get_str("errmsg").unwrap_or_default(),
+            };
+            if let Some(info) = error.get("errInfo") {
+                write_concern_error.insert("errInfo", info.clone());
+            }
+            self.write_concern_errors.push(write_concern_error);
+        }
+
+        had_write_errors
+    }
+
+    /// The reply document libmongoc produces for a bulk write.
+    fn to_reply(&self) -> RawDocumentBuf {
+        let mut reply = rawdoc! {
+            "nInserted": self.n_inserted,
+            "nMatched": self.n_matched,
+            "nModified": self.n_modified,
+            "nRemoved": self.n_removed,
+            "nUpserted": self.n_upserted,
+        };
+        if !self.upserted.is_empty() {
+            reply.append("upserted", to_raw_array(&self.upserted));
+        }
+        reply.append("writeErrors", to_raw_array(&self.write_errors));
+        if !self.write_concern_errors.is_empty() {
+            reply.append(
+                "writeConcernErrors",
+                to_raw_array(&self.write_concern_errors),
+            );
+        }
+        reply
+    }
+
+    /// The error libmongoc reports for a bulk that partially failed: write errors take
+    /// precedence over write concern errors, and multiple errors are combined into one message.
+    fn to_error(&self, error_api: i32) -> Option<MongocError> {
+        let write_domain = if error_api >= MONGOC_ERROR_API_VERSION_2 {
+            mongoc_error_domain_t::MONGOC_ERROR_SERVER
+        } else {
+            mongoc_error_domain_t::MONGOC_ERROR_COMMAND
+        };
+
+        if !self.write_errors.is_empty() {
+            Some(combine_errors(&self.write_errors, write_domain, "write"))
+        } else if !self.write_concern_errors.is_empty() {
+            Some(combine_errors(
+                &self.write_concern_errors,
+                mongoc_error_domain_t::MONGOC_ERROR_WRITE_CONCERN,
+                "write concern",
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+fn combine_errors(
+    errors: &[Document],
+    domain: mongoc_error_domain_t,
+    error_type: &str,
+) -> MongocError {
+    let code = errors.last().map(|e| get_i64(e, "code")).unwrap_or(0) as i32;
+    let message = match errors {
+        [error] => error.get_str("errmsg").unwrap_or_default().to_string(),
+        _ => {
+            let messages: Vec<_> = errors
+                .iter()
+                .map(|e| format!("\"{}\"", e.get_str("errmsg").unwrap_or_default()))
+                .collect();
+            format!("Multiple {} errors: {}", error_type, messages.join(", "))
+        }
+    };
+    MongocError::server(domain, code, message)
+}
+
+fn documents<'a>(doc: &'a Document, key: &str) -> impl Iterator<Item = &'a Document> {
+    doc.get_array(key)
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d.as_document())
+}
+
+fn to_raw_array(docs: &[Document]) -> RawArrayBuf {
+    let mut array = RawArrayBuf::new();
+    for doc in docs {
+        // Documents built from server replies always serialize.
+        if let Ok(doc) = RawDocumentBuf::from_document(doc) {
+            array.push(doc);
+        }
+    }
+    array
+}
+
+fn get_i64(doc: &Document, key: &str) -> i64 {
+    match doc.get(key) {
+        Some(Bson::Int32(i)) => *i as i64,
+        Some(Bson::Int64(i)) => *i,
+        Some(Bson::Double(d)) => *d as i64,
+        _ => 0,
+    }
+}
+
+/// Parses an update document, which may be an array of pipeline stages. Unless validation is
+/// disabled, the document must consist only of update operators.
+unsafe fn parse_update(
+    update: *const bson_t,
+    validate: Option<bson_validate_flags_t>,
+) -> anyhow::Result<Bson> {
+    let flags = validate.unwrap_or_else(bson_validate_flags_t::default_update);
+    if !flags.is_empty() {
+        // Update operators are the point of an update, so they are never rejected here.
+        bson::validate(
+            &*update,
+            flags - bson_validate_flags_t::BSON_VALIDATE_DOLLAR_KEYS,
+        )
+        .map_err(|e| anyhow::anyhow!("invalid argument for update: {}", e))?;
+    }
+
+    match (*update).into_iter().next().transpose()? {
+        Some(("0", _)) => {
+            let stages = (*update)
+                .into_iter()
+                .map(|kvp| match kvp?.1 {
+                    RawBsonRef::Document(d) => Ok(Bson::Document(d.try_into()?)),
+                    o => anyhow::bail!("expected document in update pipeline, got {:?}", o),
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            return Ok(Bson::Array(stages));
+        }
+        None => anyhow::bail!("Empty update document"),
+        Some(_) => {}
+    }
+
+    if !flags.is_empty() {
+        for kvp in (*update).into_iter() {
+            let (key, _) = kvp?;
+            if !key.starts_with('$') {
+                anyhow::bail!(
+                    "Invalid key '{}': update only works with $ operators and pipelines",
+                    key
+                );
+            }
+        }
+    }
+    Ok(Bson::Document((*update).to_document()?))
+}
+
+/// Validates a replacement document, which by default may not contain update operators.
+unsafe fn parse_replacement(
+    replacement: *const bson_t,
+    validate: Option<bson_validate_flags_t>,
+) -> anyhow::Result<RawDocumentBuf> {
+    bson::validate(
+        &*replacement,
+        validate.unwrap_or_else(bson_validate_flags_t::default_insert),
+    )
+    .map_err(|e| anyhow::anyhow!("invalid argument for replace: {}", e))?;
+    Ok((*replacement).to_raw_document_buf())
+}
+
+/// Validates a document being inserted and adds an `_id` to it if it doesn't have one, as
+/// libmongoc does.
+unsafe fn parse_insert(
+    document: *const bson_t,
+    validate: Option<bson_validate_flags_t>,
+) -> anyhow::Result<RawDocumentBuf> {
+    bson::validate(
+        &*document,
+        validate.unwrap_or_else(bson_validate_flags_t::default_insert),
+    )
+    .map_err(|e| anyhow::anyhow!("invalid document for insert: {}", e))?;
+
+    if (*document).get("_id")?.is_some() {
+        return Ok((*document).to_raw_document_buf());
+    }
+
+    let mut with_id = RawDocumentBuf::new();
+    with_id.append("_id", ObjectId::new());
+    for kvp in (*document).into_iter() {
+        let (key, value) = kvp?;
+        with_id.append(key, value.to_raw_bson());
+    }
+    Ok(with_id)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_bulk_operation_insert_with_opts(
     bulk: *mut mongoc_bulk_operation_t,
     document: *const bson_t<'static>,
-    _opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_op = match &(*bulk).operation {
-        Operation::None => Operation::InsertOne {
-            document: (*document).to_owned(),
-            options: None,
-        },
-        Operation::InsertOne {
-            document: existing_document,
-            options,
-        } => Operation::InsertMany {
-            documents: vec![existing_document.clone(), (*document).to_owned()],
-            options: None,
-        },
-        o => panic!("cant add insert one to {:?}", o),
-    };
-    (*bulk).operation = new_op;
-    true
+    catch_panic(error, || {
+        (*bulk).push(error, || {
+            let opts = OperationOpts::parse(opts, &["validate"])?;
+            Ok(WriteModel::InsertOne {
+                document: parse_insert(document, opts.validate)?,
+            })
+        })
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_bulk_operation_remove_many_with_opts(
     bulk: *mut mongoc_bulk_operation_t,
     filter: *const bson_t<'static>,
-    _opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_op = match &(*bulk).operation {
-        Operation::None => Operation::DeleteMany {
-            filter: (*filter).to_document().unwrap(),
-            options: None,
-        },
-        o => panic!("cant add insert one to {:?}", o),
-    };
-    (*bulk).operation = new_op;
-    true
+    catch_panic(error, || {
+        (*bulk).push(error, || {
+            let opts = OperationOpts::parse(opts, &["collation", "hint", "limit"])?;
+            opts.check_limit(0)?;
+            Ok(WriteModel::DeleteMany {
+                filter: (*filter).to_document()?,
+                options: opts.entry,
+            })
+        })
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_bulk_operation_remove_one_with_opts(
     bulk: *mut mongoc_bulk_operation_t,
     filter: *const bson_t<'static>,
-    _opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_op = match &(*bulk).operation {
-        Operation::None => Operation::DeleteOne {
-            filter: (*filter).to_document().unwrap(),
-            options: None,
-        },
-        o => panic!("cant add insert one to {:?}", o),
-    };
-    (*bulk).operation = new_op;
-    true
+    catch_panic(error, || {
+        (*bulk).push(error, || {
+            let opts = OperationOpts::parse(opts, &["collation", "hint", "limit"])?;
+            opts.check_limit(1)?;
+            Ok(WriteModel::DeleteOne {
+                filter: (*filter).to_document()?,
+                options: opts.entry,
+            })
+        })
+    })
 }
 
 #[no_mangle]
@@ -125,39 +848,41 @@ pub unsafe extern "C" fn mongoc_bulk_operation_replace_one_with_opts(
     bulk: *mut mongoc_bulk_operation_t,
     filter: *const bson_t<'static>,
     replacement: *const bson_t<'static>,
-    _opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_op = match &(*bulk).operation {
-        Operation::None => Operation::ReplaceOne {
-            filter: (*filter).to_document().unwrap(),
-            replacement: (*replacement).to_owned(),
-            options: None,
-        },
-        o => panic!("cant add insert one to {:?}", o),
-    };
-    (*bulk).operation = new_op;
-    true
+    catch_panic(error, || {
+        (*bulk).push(error, || {
+            let opts = OperationOpts::parse(opts, &["upsert", "collation", "hint", "validate"])?;
+            Ok(WriteModel::ReplaceOne {
+                filter: (*filter).to_document()?,
+                replacement: parse_replacement(replacement, opts.validate)?,
+                options: opts.entry,
+            })
+        })
+    })
 }
 
+const UPDATE_OPTS: &[&str] = &["upsert", "collation", "arrayFilters", "hint", "validate"];
+
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_bulk_operation_update_many_with_opts(
     bulk: *mut mongoc_bulk_operation_t,
     filter: *const bson_t<'static>,
     update: *const bson_t<'static>,
-    _opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_op = match &(*bulk).operation {
-        Operation::None => Operation::UpdateMany {
-            filter: (*filter).to_document().unwrap(),
-            update: (*update).to_document().unwrap(),
-            options: None,
-        },
-        o => panic!("cant add update many to {:?}", o),
-    };
-    (*bulk).operation = new_op;
-    true
+    catch_panic(error, || {
+        (*bulk).push(error, || {
+            let opts = OperationOpts::parse(opts, UPDATE_OPTS)?;
+            Ok(WriteModel::UpdateMany {
+                filter: (*filter).to_document()?,
+                update: parse_update(update, opts.validate)?,
+                options: opts.entry,
+            })
+        })
+    })
 }
 
 #[no_mangle]
@@ -165,54 +890,367 @@ pub unsafe extern "C" fn mongoc_bulk_operation_update_one_with_opts(
     bulk: *mut mongoc_bulk_operation_t,
     filter: *const bson_t<'static>,
     update: *const bson_t<'static>,
-    _opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_op = match &(*bulk).operation {
-        Operation::None => Operation::UpdateOne {
-            filter: (*filter).to_document().unwrap(),
-            update: (*update).to_document().unwrap(),
-            options: None,
-        },
-        o => panic!("cant add update one to {:?}", o),
-    };
-    (*bulk).operation = new_op;
-    true
+    catch_panic(error, || {
+        (*bulk).push(error, || {
+            let opts = OperationOpts::parse(opts, UPDATE_OPTS)?;
+            Ok(WriteModel::UpdateOne {
+                filter: (*filter).to_document()?,
+                update: parse_update(update, opts.validate)?,
+                options: opts.entry,
+            })
+        })
+    })
 }
 
+/// Returns the id of the server the bulk ran on, or 0 on failure. A bulk that succeeded on a
+/// server that couldn't be identified, such as the mongos a transaction is pinned to, returns
+/// UNKNOWN_SERVER_ID (UINT32_MAX) rather than 0, so it isn't mistaken for a failure.
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_bulk_operation_execute(
     bulk: *mut mongoc_bulk_operation_t,
     reply: *mut bson_t<'static>,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> u32 {
-    println!("RUST DEBUG: executing bulk write");
-    let result: anyhow::Result<_> = (|| match &(*bulk).operation {
-        Operation::InsertOne { document, options } => {
-            let _insert_one = (*bulk).collection.insert_one(document, options.clone())?;
-            Ok(rawdoc! {
-                "nInserted": 1
-            })
+    catch_panic(error, || {
+        let result = match (*bulk).execute() {
+            Ok(result) => result,
+            Err(e) => {
+                if !reply.is_null() {
+                    *reply = RawDocumentBuf::new().into();
+                }
+                set_error(error, (*bulk).error_api, &e);
+                return 0;
+            }
+        };
+
+        if !reply.is_null() {
+            *reply = result.to_reply().into();
         }
-        _ => todo!(),
-    })();
-    match result {
-        Ok(r) => {
-            *reply = r.into();
-            1
+
+        if let Some(ref e) = result.error {
+            set_error(error, (*bulk).error_api, e);
+            return 0;
         }
-        Err(_) => 0,
-    }
+
+        if let Some(e) = result.to_error((*bulk).error_api) {
+            e.write_to(error);
+            return 0;
+        }
+
+        result.server_id
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn mongoc_bulk_operation_get_write_concern(
+pub unsafe extern "C" fn mongoc_bulk_operation_new(ordered: bool) -> *mut mongoc_bulk_operation_t {
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_bulk_operation_t::new(ordered)))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_client(
     bulk: *mut mongoc_bulk_operation_t,
+    client: *mut mongoc_client_t,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        (*bulk).client = Some(Client::clone(&*client));
+        (*bulk).error_api = (*client).error_api;
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_database(
+    bulk: *mut mongoc_bulk_operation_t,
+    database: *const c_char,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        (*bulk).database = Some(CStr::from_ptr(database).to_string_lossy().into_owned());
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_collection(
+    bulk: *mut mongoc_bulk_operation_t,
+    collection: *const c_char,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        (*bulk).collection = Some(CStr::from_ptr(collection).to_string_lossy().into_owned());
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_write_concern(
+    bulk: *mut mongoc_bulk_operation_t,
+    write_concern: *const mongoc_write_concern_t,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        let write_concern = match write_concern.as_ref() {
+            Some(wc) => WriteConcern::clone(wc),
+            None => WriteConcern::default(),
+        };
+        (*bulk).write_concern = write_concern.into();
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_get_write_concern(
+    bulk: *const mongoc_bulk_operation_t,
 ) -> *const mongoc_write_concern_t {
-    todo!("implement get write concern bulk")
+    catch_panic(std::ptr::null_mut(), || &(*bulk).write_concern as *const _)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_bypass_document_validation(
+    bulk: *mut mongoc_bulk_operation_t,
+    bypass: bool,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        (*bulk).bypass_document_validation = Some(bypass);
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_client_session(
+    bulk: *mut mongoc_bulk_operation_t,
+    session: *mut mongoc_client_session_t,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        (*bulk).session = session;
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_comment(
+    bulk: *mut mongoc_bulk_operation_t,
+    comment: *const bson_value_t,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| match comment.as_ref() {
+            Some(comment) => Ok(Some(Bson::try_from(comment.to_raw_bson()?)?)),
+            None => Ok(None),
+        })();
+
+        match result {
+            Ok(comment) => (*bulk).comment = comment,
+            Err(e) => (*bulk).error = Some(e),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_let(
+    bulk: *mut mongoc_bulk_operation_t,
+    let_vars: *const bson_t<'static>,
+) {
+    catch_panic(std::ptr::null_mut(), || match (*let_vars).to_document() {
+        Ok(let_vars) => (*bulk).let_vars = Some(let_vars),
+        Err(e) => (*bulk).error = Some(e),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_set_hint(
+    bulk: *mut mongoc_bulk_operation_t,
+    server_id: u32,
+) {
+    catch_panic(std::ptr::null_mut(), || {
+        (*bulk).server_id = server_id;
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_bulk_operation_get_hint(
+    bulk: *const mongoc_bulk_operation_t,
+) -> u32 {
+    catch_panic(std::ptr::null_mut(), || (*bulk).server_id)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_bulk_operation_destroy(bulk: *mut mongoc_bulk_operation_t) {
-    drop(Box::from_raw(bulk))
+    catch_panic(std::ptr::null_mut(), || drop(Box::from_raw(bulk)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::mongoc_error_code_t, MONGOC_ERROR_API_VERSION_LEGACY};
+
+    #[test]
+    fn merge_counts_each_kind() {
+        let mut result = BulkResult::default();
+        assert!(!result.merge(CommandKind::Insert, &doc! { "n": 3, "ok": 1 }, 0));
+        assert!(!result.merge(
+            CommandKind::Update,
+            &doc! { "n": 2, "nModified": 1, "ok": 1 },
+            3
+        ));
+        assert!(!result.merge(CommandKind::Delete, &doc! { "n": 4_i64, "ok": 1.0 }, 5));
+
+        assert_eq!(result.n_inserted, 3);
+        assert_eq!(result.n_matched, 2);
+        assert_eq!(result.n_modified, 1);
+        assert_eq!(result.n_removed, 4);
+        assert_eq!(result.n_upserted, 0);
+    }
+
+    #[test]
+    fn merge_remaps_upserted_indexes() {
+        let mut result = BulkResult::default();
+        let reply = doc! {
+            "n": 3,
+            "nModified": 1,
+            "upserted": [
+                { "index": 0, "_id": 10 },
+                { "index": 2, "_id": "b" },
+            ],
+            "ok": 1,
+        };
+        assert!(!result.merge(CommandKind::Update, &reply, 4));
+
+        assert_eq!(result.n_upserted, 2);
+        assert_eq!(result.n_matched, 1);
+        assert_eq!(
+            result.upserted,
+            vec![
+                doc! { "index": 4, "_id": 10 },
+                doc! { "index": 6, "_id": "b" },
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_remaps_write_error_indexes() {
+        let mut result = BulkResult::default();
+        let first = doc! {
+            "n": 1,
+            "writeErrors": [{ "index": 1, "code": 11000, "errmsg": "duplicate key" }],
+            "ok": 1,
+        };
+        let second = doc! {
+            "n": 0,
+            "writeErrors": [{
+                "index": 0,
+                "code": 121,
+                "errmsg": "validation failed",
+                "errInfo": { "details": "x" },
+            }],
+            "ok": 1,
+        };
+        assert!(result.merge(CommandKind::Insert, &first, 0));
+        assert!(result.merge(CommandKind::Insert, &second, 2));
+
+        assert_eq!(result.n_inserted, 1);
+        assert_eq!(
+            result.write_errors,
+            vec![
+                doc! { "index": 1, "code": 11000, "errmsg": "duplicate key" },
+                doc! {
+                    "index": 2,
+                    "code": 121,
+                    "errmsg": "validation failed",
+                    "errInfo": { "details": "x" },
+                },
+            ]
+        );
+
+        let error = result.to_error(MONGOC_ERROR_API_VERSION_2).unwrap();
+        assert_eq!(
+            error.domain,
+            mongoc_error_domain_t::MONGOC_ERROR_SERVER as u32
+        );
+        assert_eq!(error.code, 121);
+        assert_eq!(
+            error.message,
+            "Multiple write errors: \"duplicate key\", \"validation failed\""
+        );
+    }
+
+    #[test]
+    fn merge_collects_write_concern_errors() {
+        let mut result = BulkResult::default();
+        let reply = doc! {
+            "n": 1,
+            "writeConcernError": { "code": 64, "errmsg": "waiting for replication timed out" },
+            "ok": 1,
+        };
+        assert!(!result.merge(CommandKind::Delete, &reply, 0));
+
+        assert_eq!(
+            result.write_concern_errors,
+            vec![doc! { "code": 64, "errmsg": "waiting for replication timed out" }]
+        );
+        let error = result.to_error(MONGOC_ERROR_API_VERSION_LEGACY).unwrap();
+        assert_eq!(
+            error.domain,
+            mongoc_error_domain_t::MONGOC_ERROR_WRITE_CONCERN as u32
+        );
+        assert_eq!(error.code, 64);
+        assert_eq!(error.message, "waiting for replication timed out");
+    }
+
+    #[test]
+    fn reply_reports_merged_batches() {
+        let mut result = BulkResult::default();
+        result.merge(CommandKind::Insert, &doc! { "n": 2, "ok": 1 }, 0);
+        result.merge(
+            CommandKind::Update,
+            &doc! { "n": 1, "nModified": 0, "upserted": [{ "index": 0, "_id": 1 }], "ok": 1 },
+            2,
+        );
+
+        let reply: Document = result.to_reply().to_document().unwrap();
+        assert_eq!(
+            reply,
+            doc! {
+                "nInserted": 2,
+                "nMatched": 0,
+                "nModified": 0,
+                "nRemoved": 0,
+                "nUpserted": 1,
+                "upserted": [{ "index": 2, "_id": 1 }],
+                "writeErrors": [],
+            }
+        );
+        assert!(result.to_error(MONGOC_ERROR_API_VERSION_2).is_none());
+    }
+
+    #[test]
+    fn parse_validate_option() {
+        let parse = |opts: RawDocumentBuf| unsafe {
+            OperationOpts::parse(&bson_t::from(opts), &["validate"]).map(|o| o.validate)
+        };
+
+        assert_eq!(
+            parse(rawdoc! { "validate": false }).unwrap(),
+            Some(bson_validate_flags_t::BSON_VALIDATE_NONE)
+        );
+        assert_eq!(
+            parse(rawdoc! { "validate": 3 }).unwrap(),
+            Some(
+                bson_validate_flags_t::BSON_VALIDATE_UTF8
+                    | bson_validate_flags_t::BSON_VALIDATE_DOLLAR_KEYS
+            )
+        );
+
+        for invalid in [
+            rawdoc! { "validate": true },
+            rawdoc! { "validate": 1 << 5 },
+            rawdoc! { "validate": -1 },
+        ] {
+            let error =
+                MongocError::from_anyhow(&parse(invalid).unwrap_err(), MONGOC_ERROR_API_VERSION_2);
+            assert_eq!(
+                error.domain,
+                mongoc_error_domain_t::MONGOC_ERROR_COMMAND as u32
+            );
+            assert_eq!(
+                error.code,
+                mongoc_error_code_t::MONGOC_ERROR_COMMAND_INVALID_ARG as u32
+            );
+        }
+    }
 }
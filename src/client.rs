@@ -1,24 +1,137 @@
-use std::{ffi::CStr, ops::Deref, os::raw::c_char};
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    ops::Deref,
+    os::raw::c_char,
+    sync::{Arc, Mutex},
+};
 
 use mongodb::{
     bson::{Document, RawBsonRef, RawDocumentBuf},
+    options::{ClientOptions, ServerAddress},
     sync::Client,
 };
 
 use crate::{
+    api::mongoc_server_api_t,
     bson::{bson_error_t, bson_t},
+    client_pool::PoolSlot,
     collection::mongoc_collection_t,
     cursor::mongoc_cursor_t,
-    database::{mongoc_database_command_with_opts, mongoc_database_destroy, mongoc_database_t},
+    database::{
+        mongoc_database_destroy, mongoc_database_read_command_with_opts, mongoc_database_t,
+    },
+    error::{
+        catch_panic, log_unsupported, mongoc_error_code_t, mongoc_error_domain_t, set_error,
+        MongocError,
+    },
     read_concern::mongoc_read_concern_t,
     read_pref::mongoc_read_prefs_t,
     session::{mongoc_client_session_t, mongoc_session_opt_t},
+    uri::{mongoc_uri_new_with_error, mongoc_uri_t},
     write_concern::mongoc_write_concern_t,
+    MONGOC_ERROR_API_VERSION_2, MONGOC_ERROR_API_VERSION_LEGACY,
 };
 
+/// The ids given to servers, by address, since the driver doesn't number them. Ids start at 1,
+/// as 0 means no server, and stay the same for the life of the process.
+static SERVER_IDS: Mutex<Option<HashMap<ServerAddress, u32>>> = Mutex::new(None);
+
+/// The id reported for a server that was used but couldn't be identified, which happens when the
+/// driver bypasses server selection for a session pinned to a mongos in a transaction. It's never
+/// given to a real server, so selecting it as a hint matches nothing.
+pub(crate) const UNKNOWN_SERVER_ID: u32 = u32::MAX;
+
+/// Returns the id of the server at `address`, giving it the next id if it hasn't got one yet.
+pub(crate) fn server_id(address: &ServerAddress) -> u32 {
+    let mut ids = SERVER_IDS.lock().unwrap();
+    let ids = ids.get_or_insert_with(HashMap::new);
+    let next_id = ids.len() as u32 + 1;
+    *ids.entry(address.clone()).or_insert(next_id)
+}
+
 #[allow(non_camel_case_types)]
 pub struct mongoc_client_t {
     client: Client,
+    /// The URI the client was created from, which pooled clients share with their pool.
+    uri: Arc<mongoc_uri_t>,
+    /// The options the client was built with, so it can be rebuilt by
+    /// mongoc_client_set_server_api. Pooled clients are built by their pool and don't have any.
+    options: Option<ClientOptions>,
+    pub(crate) error_api: i32,
+    error_api_set: bool,
+    server_api_set: bool,
+    /// For a pooled client, its place in the pool, which is given up if the client is destroyed
+    /// instead of being pushed back.
+    pool_slot: Option<PoolSlot>,
+    pub(crate) read_concern: mongoc_read_concern_t,
+    pub(crate) write_concern: mongoc_write_concern_t,
+    pub(crate) read_prefs: mongoc_read_prefs_t,
+}
+
+impl mongoc_client_t {
+    /// Creates a handle for `client` whose read concern, write concern and read preferences start
+    /// out as the ones the client was configured with.
+    fn new(
+        client: Client,
+        uri: Arc<mongoc_uri_t>,
+        options: Option<ClientOptions>,
+        error_api: i32,
+    ) -> Self {
+        let pooled = options.is_none();
+        Self {
+            read_concern: client.read_concern().cloned().into(),
+            write_concern: client.write_concern().cloned().unwrap_or_default().into(),
+            read_prefs: client.selection_criteria().into(),
+            client,
+            uri,
+            options,
+            error_api,
+            error_api_set: pooled,
+            server_api_set: false,
+            pool_slot: None,
+        }
+    }
+
+    /// Creates a standalone client from the options stored in `uri`, including any changed with
+    /// the mongoc_uri_set_* functions since it was parsed.
+    pub(crate) fn from_uri(uri: &mongoc_uri_t) -> anyhow::Result<Self> {
+        let options = uri.client_options()?;
+        let client = Client::with_options(options.clone())?;
+        Ok(Self::new(
+            client,
+            Arc::new(uri.clone()),
+            Some(options),
+            MONGOC_ERROR_API_VERSION_LEGACY,
+        ))
+    }
+
+    /// Creates a handle for a client popped from a pool, which inherits the pool's URI and error
+    /// API.
+    pub(crate) fn pooled(
+        client: Client,
+        uri: Arc<mongoc_uri_t>,
+        error_api: i32,
+        slot: PoolSlot,
+    ) -> Self {
+        Self {
+            pool_slot: Some(slot),
+            ..Self::new(client, uri, None, error_api)
+        }
+    }
+
+    /// Restores the settings a handle was created with, for a pooled client that's been pushed
+    /// back to its pool.
+    pub(crate) fn reset(&mut self) {
+        self.read_concern = self.client.read_concern().cloned().into();
+        self.write_concern = self
+            .client
+            .write_concern()
+            .cloned()
+            .unwrap_or_default()
+            .into();
+        self.read_prefs = self.client.selection_criteria().into();
+    }
 }
 
 impl Deref for mongoc_client_t {
@@ -29,21 +142,133 @@ impl Deref for mongoc_client_t {
     }
 }
 
-impl From<Client> for mongoc_client_t {
-    fn from(client: Client) -> Self {
-        Self { client }
-    }
-}
+/// The URI libmongoc connects to when mongoc_client_new is given NULL.
+const DEFAULT_URI: &[u8] = b"mongodb://127.0.0.1/\0";
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_new(uri_str: *const c_char) -> *mut mongoc_client_t {
-    if uri_str.is_null() {
-        panic!("null uri string")
-    }
+    catch_panic(std::ptr::null_mut(), || {
+        let uri_str = if uri_str.is_null() {
+            DEFAULT_URI.as_ptr() as *const c_char
+        } else {
+            uri_str
+        };
 
-    let uri = CStr::from_ptr(uri_str);
-    let client = Client::with_uri_str(uri.to_string_lossy().as_ref()).unwrap();
-    Box::into_raw(Box::new(mongoc_client_t { client }))
+        let uri = mongoc_uri_new_with_error(uri_str, std::ptr::null_mut());
+        if uri.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        let client = mongoc_client_new_from_uri(uri);
+        drop(Box::from_raw(uri));
+        client
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_new_from_uri(
+    uri: *const mongoc_uri_t,
+) -> *mut mongoc_client_t {
+    catch_panic(std::ptr::null_mut(), || {
+        mongoc_client_new_from_uri_with_error(uri, std::ptr::null_mut())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_new_from_uri_with_error(
+    uri: *const mongoc_uri_t,
+    error: *mut bson_error_t,
+) -> *mut mongoc_client_t {
+    catch_panic(error, || {
+        let result = match uri.as_ref() {
+            Some(uri) => mongoc_client_t::from_uri(uri),
+            None => Err(anyhow::anyhow!("Cannot create a client from a NULL URI")),
+        };
+
+        match result {
+            Ok(client) => Box::into_raw(Box::new(client)),
+            Err(e) => {
+                set_error(error, MONGOC_ERROR_API_VERSION_LEGACY, &e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_get_uri(
+    client: *const mongoc_client_t,
+) -> *const mongoc_uri_t {
+    catch_panic(std::ptr::null_mut(), || &*(*client).uri as *const _)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_set_error_api(
+    client: *mut mongoc_client_t,
+    version: i32,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        // Pooled clients get their error API from the pool via mongoc_client_pool_set_error_api.
+        if (*client).pool_slot.is_some() || (*client).error_api_set {
+            return false;
+        }
+
+        if version != MONGOC_ERROR_API_VERSION_LEGACY && version != MONGOC_ERROR_API_VERSION_2 {
+            return false;
+        }
+
+        (*client).error_api = version;
+        (*client).error_api_set = true;
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_set_server_api(
+    client: *mut mongoc_client_t,
+    api: *const mongoc_server_api_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        use mongoc_error_code_t::*;
+
+        let fail = |code, message| {
+            MongocError::new(mongoc_error_domain_t::MONGOC_ERROR_CLIENT, code, message)
+                .write_to(error);
+            false
+        };
+
+        let options = match (*client).options {
+            Some(ref options) => options,
+            None => {
+                return fail(
+                    MONGOC_ERROR_CLIENT_API_FROM_POOL,
+                    "Cannot set server api on a client checked out from a pool",
+                )
+            }
+        };
+        if (*client).server_api_set {
+            return fail(
+                mongoc_error_code_t::MONGOC_ERROR_CLIENT_API_ALREADY_SET,
+                "Cannot set server api more than once per client",
+            );
+        }
+
+        let mut options = options.clone();
+        options.server_api = Some((*api).clone());
+        match Client::with_options(options.clone()) {
+            Ok(rust_client) => {
+                (*client).client = rust_client;
+                (*client).options = Some(options);
+                (*client).server_api_set = true;
+                true
+            }
+            Err(e) => {
+                set_error(error, (*client).error_api, &e.into());
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -51,17 +276,32 @@ pub unsafe extern "C" fn mongoc_client_command_simple(
     client: *const mongoc_client_t,
     db_name: *const c_char,
     command: *const bson_t,
-    _read_pref: *const u8,
+    read_pref: *const mongoc_read_prefs_t,
     reply: *mut bson_t,
-    _error: *const u8,
-) {
-    let result = (*client)
-        .client
-        .database(CStr::from_ptr(db_name).to_str().unwrap())
-        .run_command((*command).to_document().unwrap(), None)
-        .unwrap();
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let db_name = CStr::from_ptr(db_name).to_str()?;
+            let selection_criteria = read_pref.as_ref().map(|rp| rp.selection_criteria());
+            let reply = (*client)
+                .client
+                .database(db_name)
+                .run_command((*command).to_document()?, selection_criteria)?;
+            Ok(RawDocumentBuf::from_document(&reply)?)
+        })();
 
-    *reply = RawDocumentBuf::from_document(&result).unwrap().into();
+        match result {
+            Ok(r) => {
+                *reply = r.into();
+                true
+            }
+            Err(e) => {
+                set_error(error, (*client).error_api, &e);
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -74,11 +314,14 @@ pub unsafe extern "C" fn mongoc_client_read_command_with_opts(
     reply: *mut bson_t,
     error: *mut bson_error_t,
 ) -> bool {
-    let database = mongoc_client_get_database(client, db_name);
-    let result =
-        mongoc_database_command_with_opts(database, command, read_pref, opts, reply, error);
-    mongoc_database_destroy(database);
-    result
+    catch_panic(error, || {
+        let database = mongoc_client_get_database(client, db_name);
+        let result = mongoc_database_read_command_with_opts(
+            database, command, read_pref, opts, reply, error,
+        );
+        mongoc_database_destroy(database);
+        result
+    })
 }
 
 #[no_mangle]
@@ -86,10 +329,11 @@ pub unsafe extern "C" fn mongoc_client_get_database(
     client: *mut mongoc_client_t,
     db_name: *const c_char,
 ) -> *mut mongoc_database_t {
-    let name = CStr::from_ptr(db_name).to_string_lossy();
-    println!("getting database {}", name);
-    let db = mongoc_database_t::new((*client).deref(), name);
-    Box::into_raw(Box::new(db))
+    catch_panic(std::ptr::null_mut(), || {
+        let name = CStr::from_ptr(db_name).to_string_lossy();
+        let db = mongoc_database_t::new(&*client, name);
+        Box::into_raw(Box::new(db))
+    })
 }
 
 #[no_mangle]
@@ -98,53 +342,100 @@ pub unsafe extern "C" fn mongoc_client_get_collection(
     db_name: *const c_char,
     coll_name: *const c_char,
 ) -> *mut mongoc_collection_t {
-    let db_name = CStr::from_ptr(db_name).to_string_lossy();
-    let coll_name = CStr::from_ptr(coll_name).to_string_lossy();
-    let coll = (*client)
-        .database(db_name.as_ref())
-        .collection(coll_name.as_ref());
-    Box::into_raw(Box::new(coll.into()))
+    catch_panic(std::ptr::null_mut(), || {
+        let db_name = CStr::from_ptr(db_name).to_string_lossy();
+        let coll_name = CStr::from_ptr(coll_name).to_string_lossy();
+        let db = mongoc_database_t::new(&*client, db_name);
+        let coll = mongoc_collection_t::new(&db, coll_name);
+        Box::into_raw(Box::new(coll))
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_start_session(
     client: *mut mongoc_client_t,
     _opts: *const mongoc_session_opt_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> *mut mongoc_client_session_t {
-    match (*client).start_session(None) {
-        Ok(s) => Box::into_raw(Box::new(s.into())),
-        Err(_e) => std::ptr::null_mut(),
-    }
+    catch_panic(error, || match (*client).start_session(None) {
+        Ok(s) => mongoc_client_session_t::new(s, (*client).error_api).into_raw(),
+        Err(e) => {
+            set_error(error, (*client).error_api, &e.into());
+            std::ptr::null_mut()
+        }
+    })
 }
 
+/// The driver doesn't expose server descriptions, so this logs an error and returns NULL, what
+/// libmongoc returns for an unknown server.
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_get_server_description(
-    client: *mut mongoc_client_t,
-    server_id: u32,
+    _client: *mut mongoc_client_t,
+    _server_id: u32,
 ) -> *const u8 {
-    panic!("server id not exposed by driver")
+    catch_panic(std::ptr::null_mut(), || {
+        log_unsupported(
+            "mongoc_client_get_server_description",
+            "the driver doesn't expose server descriptions",
+        );
+        std::ptr::null()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_set_read_concern(
+    client: *mut mongoc_client_t,
+    read_concern: *const mongoc_read_concern_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*client).read_concern = read_concern.as_ref().cloned().unwrap_or_default();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_get_read_concern(
     client: *mut mongoc_client_t,
 ) -> *const mongoc_read_concern_t {
-    todo!("implement this")
+    catch_panic(std::ptr::null_mut(), || &(*client).read_concern as *const _)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_set_read_prefs(
+    client: *mut mongoc_client_t,
+    read_prefs: *const mongoc_read_prefs_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*client).read_prefs = read_prefs.as_ref().cloned().unwrap_or_default();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_get_read_prefs(
     client: *mut mongoc_client_t,
 ) -> *const mongoc_read_prefs_t {
-    todo!("implement this")
+    catch_panic(std::ptr::null_mut(), || &(*client).read_prefs as *const _)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_set_write_concern(
+    client: *mut mongoc_client_t,
+    write_concern: *const mongoc_write_concern_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*client).write_concern = write_concern.as_ref().cloned().unwrap_or_default();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_get_write_concern(
     client: *mut mongoc_client_t,
 ) -> *const mongoc_write_concern_t {
-    todo!("implement this")
+    catch_panic(std::ptr::null_mut(), || {
+        &(*client).write_concern as *const _
+    })
 }
 
 #[no_mangle]
@@ -153,12 +444,16 @@ pub unsafe extern "C" fn mongoc_client_watch(
     pipeline: *const bson_t<'static>,
     opts: *const bson_t<'static>,
 ) -> *const mongoc_cursor_t {
-    panic!("driver doesnt have change streams yet")
+    catch_panic(std::ptr::null_mut(), || {
+        panic!("driver doesnt have change streams yet")
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_destroy(client: *mut mongoc_client_t) {
-    drop(Box::from_raw(client));
+    catch_panic(std::ptr::null_mut(), || {
+        drop(Box::from_raw(client));
+    })
 }
 
 pub(crate) unsafe fn make_agg_pipeline(pipeline: *const bson_t) -> anyhow::Result<Vec<Document>> {
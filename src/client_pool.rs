@@ -1,24 +1,150 @@
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use mongodb::sync::Client;
+use mongodb::{options::ClientOptions, sync::Client};
 
 use crate::{
     api::mongoc_server_api_t,
     bson::bson_error_t,
-    client::{mongoc_client_destroy, mongoc_client_t},
+    client::mongoc_client_t,
+    error::{catch_panic, mongoc_error_code_t, mongoc_error_domain_t, set_error, MongocError},
     uri::mongoc_uri_t,
+    MONGOC_ERROR_API_VERSION_2, MONGOC_ERROR_API_VERSION_LEGACY,
 };
 
+/// The pool size libmongoc uses when the URI has no maxPoolSize.
+const DEFAULT_MAX_POOL_SIZE: u32 = 100;
+
 pub struct mongoc_client_pool_t {
     rust_client: Client,
+    /// The pool's URI, which is shared by the clients it hands out.
+    uri: Arc<mongoc_uri_t>,
+    options: ClientOptions,
+    error_api: i32,
+    error_api_set: bool,
+    server_api_set: bool,
+    client_popped: AtomicBool,
+    /// The most client handles that can be checked out at once, or 0 for no limit.
+    max_pool_size: u32,
+    wait_queue_timeout: Option<Duration>,
+    shared: Arc<PoolShared>,
+}
+
+/// The state of the pool that its handles refer back to.
+#[derive(Default)]
+struct PoolShared {
+    state: Mutex<PoolState>,
+    /// Notified when a handle is pushed back or destroyed, so a slot may have become free.
+    client_pushed: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// Handles that have been pushed back and can be handed out again.
+    idle: Vec<Box<mongoc_client_t>>,
+    /// How many handles the pool has created and not yet destroyed, whether idle or checked out.
+    size: u32,
 }
 
-impl From<Client> for mongoc_client_pool_t {
-    fn from(client: Client) -> Self {
-        Self {
-            rust_client: client,
+/// A handle's place in the pool's size, which is given up when the handle is dropped.
+pub(crate) struct PoolSlot(Arc<PoolShared>);
+
+impl Drop for PoolSlot {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().size -= 1;
+        self.0.client_pushed.notify_one();
+    }
+}
+
+impl mongoc_client_pool_t {
+    fn new(uri: &mongoc_uri_t) -> anyhow::Result<Self> {
+        let options = uri.client_options()?;
+        Ok(Self {
+            rust_client: Client::with_options(options.clone())?,
+            uri: Arc::new(uri.clone()),
+            max_pool_size: options.max_pool_size.unwrap_or(DEFAULT_MAX_POOL_SIZE),
+            options,
+            error_api: MONGOC_ERROR_API_VERSION_LEGACY,
+            error_api_set: false,
+            server_api_set: false,
+            client_popped: AtomicBool::new(false),
+            wait_queue_timeout: uri.wait_queue_timeout(),
+            shared: Default::default(),
+        })
+    }
+
+    /// Checks out a client handle, reusing an idle one if there is one. Once maxPoolSize handles
+    /// are checked out this waits for one to be pushed back, up to waitQueueTimeoutMS, if `block`
+    /// is set and returns None otherwise.
+    fn pop(&self, block: bool) -> Option<Box<mongoc_client_t>> {
+        self.client_popped.store(true, Ordering::SeqCst);
+        let deadline = self
+            .wait_queue_timeout
+            .map(|timeout| Instant::now() + timeout);
+
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(client) = state.idle.pop() {
+                return Some(client);
+            }
+
+            if self.max_pool_size == 0 || state.size < self.max_pool_size {
+                // The slot is taken under the lock, but the handle is built after releasing it.
+                state.size += 1;
+                drop(state);
+                let slot = PoolSlot(Arc::clone(&self.shared));
+                let client = mongoc_client_t::pooled(
+                    self.rust_client.clone(),
+                    Arc::clone(&self.uri),
+                    self.error_api,
+                    slot,
+                );
+                return Some(Box::new(client));
+            }
+
+            if !block {
+                return None;
+            }
+
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.shared
+                        .client_pushed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.shared.client_pushed.wait(state).unwrap(),
+            };
         }
     }
+
+    /// Returns a checked out handle to the pool, undoing any settings changed on it.
+    fn push(&self, mut client: Box<mongoc_client_t>) {
+        client.reset();
+        self.shared.state.lock().unwrap().idle.push(client);
+        self.shared.client_pushed.notify_one();
+    }
+}
+
+impl Drop for mongoc_client_pool_t {
+    fn drop(&mut self) {
+        // The idle handles refer back to the shared state that holds them, so they're dropped
+        // here to break the cycle. The lock is released first since they take it to give up
+        // their slots.
+        let idle = std::mem::take(&mut self.shared.state.lock().unwrap().idle);
+        drop(idle);
+    }
 }
 
 impl Deref for mongoc_client_pool_t {
@@ -33,16 +159,51 @@ impl Deref for mongoc_client_pool_t {
 pub unsafe extern "C" fn mongoc_client_pool_new(
     uri: *const mongoc_uri_t,
 ) -> *mut mongoc_client_pool_t {
-    let client = Client::with_uri_str((*uri).as_str()).unwrap();
-    Box::into_raw(Box::new(client.into()))
+    mongoc_client_pool_new_with_error(uri, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_client_pool_new_with_error(
+    uri: *const mongoc_uri_t,
+    error: *mut bson_error_t,
+) -> *mut mongoc_client_pool_t {
+    catch_panic(error, || {
+        let result = match uri.as_ref() {
+            Some(uri) => mongoc_client_pool_t::new(uri),
+            None => Err(anyhow::anyhow!(
+                "Cannot create a client pool from a NULL URI"
+            )),
+        };
+
+        match result {
+            Ok(pool) => Box::into_raw(Box::new(pool)),
+            Err(e) => {
+                set_error(error, MONGOC_ERROR_API_VERSION_LEGACY, &e);
+                std::ptr::null_mut()
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_pool_set_error_api(
-    _pool: *mut mongoc_client_pool_t,
-    _api: i32,
+    pool: *mut mongoc_client_pool_t,
+    api: i32,
 ) -> bool {
-    true
+    catch_panic(std::ptr::null_mut(), || {
+        if api != MONGOC_ERROR_API_VERSION_LEGACY && api != MONGOC_ERROR_API_VERSION_2 {
+            return false;
+        }
+
+        // Clients that have already been handed out would be left on the old version.
+        if (*pool).error_api_set || (*pool).client_popped.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        (*pool).error_api = api;
+        (*pool).error_api_set = true;
+        true
+    })
 }
 
 #[no_mangle]
@@ -50,43 +211,90 @@ pub unsafe extern "C" fn mongoc_client_pool_set_min_heartbeat_frequency_msec(
     _pool: *mut mongoc_client_pool_t,
     _ms: u64,
 ) -> bool {
-    // TODO: implement
-    true
+    catch_panic(std::ptr::null_mut(), || {
+        // TODO: implement
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_pool_set_server_api(
-    _pool: *mut mongoc_client_pool_t,
-    _api: *const mongoc_server_api_t,
-    _error: *mut bson_error_t,
+    pool: *mut mongoc_client_pool_t,
+    api: *const mongoc_server_api_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    // TODO: implement
-    true
+    catch_panic(error, || {
+        use mongoc_error_code_t::*;
+
+        let fail = |code, message| {
+            MongocError::new(mongoc_error_domain_t::MONGOC_ERROR_POOL, code, message)
+                .write_to(error);
+            false
+        };
+
+        if (*pool).server_api_set {
+            return fail(
+                MONGOC_ERROR_POOL_API_ALREADY_SET,
+                "Cannot set server api more than once per pool",
+            );
+        }
+        if (*pool).client_popped.load(Ordering::SeqCst) {
+            return fail(
+                MONGOC_ERROR_POOL_API_TOO_LATE,
+                "Cannot set server api after a client has been created",
+            );
+        }
+
+        let mut options = (*pool).options.clone();
+        options.server_api = Some((*api).clone());
+        match Client::with_options(options.clone()) {
+            Ok(client) => {
+                (*pool).rust_client = client;
+                (*pool).options = options;
+                (*pool).server_api_set = true;
+                true
+            }
+            Err(e) => {
+                set_error(error, (*pool).error_api, &e.into());
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_pool_pop(
     pool: *mut mongoc_client_pool_t,
 ) -> *mut mongoc_client_t {
-    Box::into_raw(Box::new((*pool).clone().into()))
+    catch_panic(std::ptr::null_mut(), || match (*pool).pop(true) {
+        Some(client) => Box::into_raw(client),
+        None => std::ptr::null_mut(),
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_pool_try_pop(
     pool: *mut mongoc_client_pool_t,
 ) -> *mut mongoc_client_t {
-    mongoc_client_pool_pop(pool)
+    catch_panic(std::ptr::null_mut(), || match (*pool).pop(false) {
+        Some(client) => Box::into_raw(client),
+        None => std::ptr::null_mut(),
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_pool_push(
-    _pool: *mut mongoc_client_pool_t,
+    pool: *mut mongoc_client_pool_t,
     client: *mut mongoc_client_t,
 ) {
-    mongoc_client_destroy(client)
+    catch_panic(std::ptr::null_mut(), || {
+        if !client.is_null() {
+            (*pool).push(Box::from_raw(client))
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_pool_destroy(pool: *mut mongoc_client_pool_t) {
-    drop(Box::from_raw(pool))
+    catch_panic(std::ptr::null_mut(), || drop(Box::from_raw(pool)))
 }
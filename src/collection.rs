@@ -1,37 +1,97 @@
-use std::{borrow::Borrow, ffi::CStr, marker::PhantomData, ops::Deref, os::raw::c_char, ptr};
+use std::{
+    borrow::Borrow,
+    ffi::{CStr, CString},
+    marker::PhantomData,
+    ops::Deref,
+    os::raw::c_char,
+    time::Duration,
+};
 
 use mongodb::{
     bson::{doc, Document, RawBsonRef, RawDocument, RawDocumentBuf},
+    error::{ErrorKind, WriteFailure},
     options::{
-        AggregateOptions, CountOptions, EstimatedDocumentCountOptions, FindOptions,
-        InsertOneOptions, ListIndexesOptions,
+        AggregateOptions, CollectionOptions, CountOptions, CursorType, DeleteOptions,
+        DistinctOptions, EstimatedDocumentCountOptions, FindOptions, Hint, InsertManyOptions,
+        InsertOneOptions, ListIndexesOptions, ReadPreference, ReplaceOptions, SelectionCriteria,
+        UpdateModifications, UpdateOptions, WriteConcern,
     },
-    sync::{Collection, Database},
+    results::UpdateResult,
+    sync::{Client, ClientSession, Collection},
 };
 
 use crate::{
-    bson::{bson_error_t, bson_t},
+    bson::{self, bson_error_t, bson_t, bson_validate_flags_t},
     bulk::mongoc_bulk_operation_t,
     client::{make_agg_pipeline, mongoc_client_t},
     cursor::mongoc_cursor_t,
-    database::mongoc_database_t,
-    find_and_modify::mongoc_find_and_modify_opts_t,
-    mongoc_query_flags_t,
+    database::{self, mongoc_database_t},
+    error::{catch_panic, set_error},
+    find_and_modify::{
+        mongoc_find_and_modify_flags_t, mongoc_find_and_modify_opts_destroy,
+        mongoc_find_and_modify_opts_new, mongoc_find_and_modify_opts_set_fields,
+        mongoc_find_and_modify_opts_set_flags, mongoc_find_and_modify_opts_set_sort,
+        mongoc_find_and_modify_opts_set_update, mongoc_find_and_modify_opts_t,
+    },
+    index::{self, mongoc_index_model_t, mongoc_index_opt_t},
+    mongoc_insert_flags_t, mongoc_query_flags_t, mongoc_remove_flags_t, mongoc_update_flags_t,
     read_concern::mongoc_read_concern_t,
-    read_pref::mongoc_read_prefs_t,
+    read_pref::{mongoc_read_prefs_t, resolve_selection_criteria},
+    session::mongoc_client_session_t,
     write_concern::mongoc_write_concern_t,
 };
 
 #[allow(non_camel_case_types)]
 pub struct mongoc_collection_t {
     rust_collection: Collection<RawDocumentBuf>,
+    pub(crate) client: Client,
+    pub(crate) error_api: i32,
+    pub(crate) read_concern: mongoc_read_concern_t,
+    pub(crate) write_concern: mongoc_write_concern_t,
+    pub(crate) read_prefs: mongoc_read_prefs_t,
 }
 
 impl mongoc_collection_t {
-    pub(crate) fn new(database: &Database, name: impl AsRef<str>) -> mongoc_collection_t {
-        mongoc_collection_t {
+    /// Creates a handle for the collection `name`, inheriting the database's read concern, write
+    /// concern and read preferences.
+    pub(crate) fn new(database: &mongoc_database_t, name: impl AsRef<str>) -> mongoc_collection_t {
+        let mut collection = mongoc_collection_t {
             rust_collection: database.collection(name.as_ref()),
-        }
+            client: database.client.clone(),
+            error_api: database.error_api,
+            read_concern: database.read_concern.clone(),
+            write_concern: database.write_concern.clone(),
+            read_prefs: database.read_prefs.clone(),
+        };
+        collection.apply_options();
+        collection
+    }
+
+    /// Rebuilds the underlying collection so it uses the handle's current read concern, write
+    /// concern and read preferences.
+    fn apply_options(&mut self) {
+        let mut options = CollectionOptions::default();
+        options.selection_criteria = Some(self.read_prefs.selection_criteria());
+        options.read_concern = self.read_concern.rust_read_concern().cloned();
+        options.write_concern = self.write_concern.rust_write_concern().cloned();
+
+        let namespace = self.rust_collection.namespace();
+        self.rust_collection = self
+            .client
+            .database(&namespace.db)
+            .collection_with_options(&namespace.coll, options);
+    }
+}
+
+impl mongoc_collection_t {
+    /// Runs `command` against the collection's database with `opts` appended, in the session
+    /// named by the opts' "sessionId", if any.
+    unsafe fn run_command_with_opts(
+        &self,
+        command: Document,
+        opts: *const bson_t,
+    ) -> anyhow::Result<Document> {
+        database::run_command_with_opts(&self.database(), command, None, opts)
     }
 }
 
@@ -43,55 +103,67 @@ impl Deref for mongoc_collection_t {
     }
 }
 
-impl From<Collection<RawDocumentBuf>> for mongoc_collection_t {
-    fn from(c: Collection<RawDocumentBuf>) -> Self {
-        Self { rust_collection: c }
-    }
-}
-
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_set_read_concern(
-    collection: *const mongoc_collection_t,
+    collection: *mut mongoc_collection_t,
     rc: *const mongoc_read_concern_t,
 ) -> bool {
-    panic!("cant set read concern after creation")
+    catch_panic(std::ptr::null_mut(), || {
+        (*collection).read_concern = rc.as_ref().cloned().unwrap_or_default();
+        (*collection).apply_options();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_get_read_concern(
     collection: *const mongoc_collection_t,
 ) -> *const mongoc_read_concern_t {
-    todo!("get read concern after creation")
+    catch_panic(std::ptr::null_mut(), || {
+        &(*collection).read_concern as *const _
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_set_write_concern(
-    collection: *const mongoc_collection_t,
-    rc: *const mongoc_write_concern_t,
+    collection: *mut mongoc_collection_t,
+    wc: *const mongoc_write_concern_t,
 ) -> bool {
-    panic!("cant set write concern after creation")
+    catch_panic(std::ptr::null_mut(), || {
+        (*collection).write_concern = wc.as_ref().cloned().unwrap_or_default();
+        (*collection).apply_options();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_get_write_concern(
     collection: *const mongoc_collection_t,
 ) -> *const mongoc_write_concern_t {
-    panic!("cant get write concern after creation")
+    catch_panic(std::ptr::null_mut(), || {
+        &(*collection).write_concern as *const _
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_set_read_prefs(
-    collection: *const mongoc_collection_t,
-    rc: *const mongoc_read_prefs_t,
+    collection: *mut mongoc_collection_t,
+    rp: *const mongoc_read_prefs_t,
 ) -> bool {
-    panic!("cant set read prefs after creation")
+    catch_panic(std::ptr::null_mut(), || {
+        (*collection).read_prefs = rp.as_ref().cloned().unwrap_or_default();
+        (*collection).apply_options();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_get_read_prefs(
     collection: *const mongoc_collection_t,
 ) -> *const mongoc_read_prefs_t {
-    panic!("cant get read prefs after creation")
+    catch_panic(std::ptr::null_mut(), || {
+        &(*collection).read_prefs as *const _
+    })
 }
 
 #[no_mangle]
@@ -100,27 +172,339 @@ pub unsafe extern "C" fn mongoc_collection_insert_one(
     document: *const bson_t,
     options: *const bson_t,
     reply: *mut bson_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    let result: anyhow::Result<_> = (|| {
-        let options: Option<InsertOneOptions> = if !options.is_null() {
-            Some(mongodb::bson::from_slice((*options).as_bytes())?)
-        } else {
-            None
-        };
-        let result = (*collection)
-            .clone_with_type::<&RawDocument>()
-            .insert_one((*document).deref(), options)?;
-        Ok(mongodb::bson::to_raw_document_buf(&result)?)
-    })();
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let options: Option<InsertOneOptions> = if !options.is_null() {
+                Some(mongodb::bson::from_slice((*options).as_bytes())?)
+            } else {
+                None
+            };
+            let result = (*collection)
+                .clone_with_type::<&RawDocument>()
+                .insert_one((*document).deref(), options)?;
+            Ok(mongodb::bson::to_raw_document_buf(&result)?)
+        })();
 
-    match result {
-        Ok(r) => {
-            *reply = r.into();
-            true
+        match result {
+            Ok(r) => {
+                *reply = r.into();
+                true
+            }
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
         }
-        Err(e) => false,
-    }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_insert_many(
+    collection: *mut mongoc_collection_t,
+    documents: *mut *const bson_t,
+    n_documents: usize,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        run_write(collection, opts, reply, error, |session| {
+            let options: Option<InsertManyOptions> = opts
+                .as_ref()
+                .map(|o| mongodb::bson::from_slice(o.as_bytes()))
+                .transpose()?;
+            let documents = std::slice::from_raw_parts(documents, n_documents)
+                .iter()
+                .map(|d| (**d).deref());
+
+            let collection = (*collection).clone_with_type::<&RawDocument>();
+            let result = match session {
+                Some(s) => collection.insert_many_with_session(documents, options, s)?,
+                None => collection.insert_many(documents, options)?,
+            };
+            Ok(doc! { "insertedCount": result.inserted_ids.len() as i32 })
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_update_one(
+    collection: *mut mongoc_collection_t,
+    selector: *const bson_t,
+    update: *const bson_t,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        run_write(collection, opts, reply, error, |session| {
+            let options: Option<UpdateOptions> = opts
+                .as_ref()
+                .map(|o| mongodb::bson::from_slice(o.as_bytes()))
+                .transpose()?;
+            let filter = (*selector).to_document()?;
+            let update = update_modifications(&*update)?;
+
+            let result = match session {
+                Some(s) => (*collection).update_one_with_session(filter, update, options, s)?,
+                None => (*collection).update_one(filter, update, options)?,
+            };
+            Ok(update_reply(result))
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_update_many(
+    collection: *mut mongoc_collection_t,
+    selector: *const bson_t,
+    update: *const bson_t,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        run_write(collection, opts, reply, error, |session| {
+            let options: Option<UpdateOptions> = opts
+                .as_ref()
+                .map(|o| mongodb::bson::from_slice(o.as_bytes()))
+                .transpose()?;
+            let filter = (*selector).to_document()?;
+            let update = update_modifications(&*update)?;
+
+            let result = match session {
+                Some(s) => (*collection).update_many_with_session(filter, update, options, s)?,
+                None => (*collection).update_many(filter, update, options)?,
+            };
+            Ok(update_reply(result))
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_replace_one(
+    collection: *mut mongoc_collection_t,
+    selector: *const bson_t,
+    replacement: *const bson_t,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        run_write(collection, opts, reply, error, |session| {
+            let options: Option<ReplaceOptions> = opts
+                .as_ref()
+                .map(|o| mongodb::bson::from_slice(o.as_bytes()))
+                .transpose()?;
+            let filter = (*selector).to_document()?;
+            for kvp in (*replacement).deref() {
+                let (key, _) = kvp?;
+                if key.starts_with('$') {
+                    anyhow::bail!(
+                        "invalid argument for replace: keys cannot begin with \"$\": \"{}\"",
+                        key
+                    );
+                }
+            }
+
+            let collection = (*collection).clone_with_type::<&RawDocument>();
+            let replacement = (*replacement).deref();
+            let result = match session {
+                Some(s) => collection.replace_one_with_session(filter, replacement, options, s)?,
+                None => collection.replace_one(filter, replacement, options)?,
+            };
+            Ok(update_reply(result))
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_delete_one(
+    collection: *mut mongoc_collection_t,
+    selector: *const bson_t,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        run_write(collection, opts, reply, error, |session| {
+            let options: Option<DeleteOptions> = opts
+                .as_ref()
+                .map(|o| mongodb::bson::from_slice(o.as_bytes()))
+                .transpose()?;
+            let filter = (*selector).to_document()?;
+
+            let result = match session {
+                Some(s) => (*collection).delete_one_with_session(filter, options, s)?,
+                None => (*collection).delete_one(filter, options)?,
+            };
+            Ok(doc! { "deletedCount": result.deleted_count as i32 })
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_delete_many(
+    collection: *mut mongoc_collection_t,
+    selector: *const bson_t,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        run_write(collection, opts, reply, error, |session| {
+            let options: Option<DeleteOptions> = opts
+                .as_ref()
+                .map(|o| mongodb::bson::from_slice(o.as_bytes()))
+                .transpose()?;
+            let filter = (*selector).to_document()?;
+
+            let result = match session {
+                Some(s) => (*collection).delete_many_with_session(filter, options, s)?,
+                None => (*collection).delete_many(filter, options)?,
+            };
+            Ok(doc! { "deletedCount": result.deleted_count as i32 })
+        })
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_insert(
+    collection: *mut mongoc_collection_t,
+    flags: mongoc_insert_flags_t,
+    document: *const bson_t,
+    write_concern: *const mongoc_write_concern_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            if !flags.contains(mongoc_insert_flags_t::MONGOC_INSERT_NO_VALIDATE) {
+                bson::validate(&*document, bson_validate_flags_t::default_insert())
+                    .map_err(|e| anyhow::anyhow!("invalid document for insert: {}", e))?;
+            }
+
+            let mut options = InsertOneOptions::default();
+            options.write_concern = write_concern.as_ref().map(|wc| WriteConcern::clone(wc));
+            (*collection)
+                .clone_with_type::<&RawDocument>()
+                .insert_one((*document).deref(), options)?;
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_update(
+    collection: *mut mongoc_collection_t,
+    flags: mongoc_update_flags_t,
+    selector: *const bson_t,
+    update: *const bson_t,
+    write_concern: *const mongoc_write_concern_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let filter = (*selector).to_document()?;
+            let upsert = flags.contains(mongoc_update_flags_t::MONGOC_UPDATE_UPSERT);
+            let multi = flags.contains(mongoc_update_flags_t::MONGOC_UPDATE_MULTI_UPDATE);
+            let validate = !flags.contains(mongoc_update_flags_t::MONGOC_UPDATE_NO_VALIDATE);
+            let write_concern = write_concern.as_ref().map(|wc| WriteConcern::clone(wc));
+
+            let is_update = match (*update).into_iter().next().transpose()? {
+                Some((key, _)) => key.starts_with('$'),
+                None => false,
+            };
+
+            // Like libmongoc, the legacy update treats a document without update operators as
+            // a replacement.
+            if is_update {
+                if validate {
+                    bson::validate(
+                        &*update,
+                        bson_validate_flags_t::default_update()
+                            - bson_validate_flags_t::BSON_VALIDATE_DOLLAR_KEYS,
+                    )
+                    .map_err(|e| anyhow::anyhow!("invalid argument for update: {}", e))?;
+                }
+
+                let mut options = UpdateOptions::default();
+                options.upsert = Some(upsert);
+                options.write_concern = write_concern;
+                let update = update_modifications(&*update)?;
+                if multi {
+                    (*collection).update_many(filter, update, options)?;
+                } else {
+                    (*collection).update_one(filter, update, options)?;
+                }
+            } else {
+                if multi {
+                    anyhow::bail!("multi update only works with $ operators");
+                }
+                if validate {
+                    bson::validate(&*update, bson_validate_flags_t::default_insert())
+                        .map_err(|e| anyhow::anyhow!("invalid argument for replace: {}", e))?;
+                }
+
+                let mut options = ReplaceOptions::default();
+                options.upsert = Some(upsert);
+                options.write_concern = write_concern;
+                (*collection)
+                    .clone_with_type::<&RawDocument>()
+                    .replace_one(filter, (*update).deref(), options)?;
+            }
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_remove(
+    collection: *mut mongoc_collection_t,
+    flags: mongoc_remove_flags_t,
+    selector: *const bson_t,
+    write_concern: *const mongoc_write_concern_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let filter = (*selector).to_document()?;
+            let mut options = DeleteOptions::default();
+            options.write_concern = write_concern.as_ref().map(|wc| WriteConcern::clone(wc));
+
+            if flags.contains(mongoc_remove_flags_t::MONGOC_REMOVE_SINGLE_REMOVE) {
+                (*collection).delete_one(filter, options)?;
+            } else {
+                (*collection).delete_many(filter, options)?;
+            }
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -128,22 +512,69 @@ pub unsafe extern "C" fn mongoc_collection_find_with_opts(
     collection: *const mongoc_collection_t,
     filter: *const bson_t,
     options: *const bson_t,
-    _read_pref: *const mongoc_read_prefs_t,
+    read_pref: *const mongoc_read_prefs_t,
 ) -> *mut mongoc_cursor_t {
-    let result: anyhow::Result<_> = (|| {
-        let opts: Option<FindOptions> = if !options.is_null() {
-            Some(mongodb::bson::from_slice((*options).as_bytes())?)
-        } else {
-            None
-        };
-        let result = (*collection).find((*filter).to_document()?, opts)?;
-        Ok(Box::into_raw(Box::new(mongoc_cursor_t::new(result))))
-    })();
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| {
+            let mut opts: FindOptions = if !options.is_null() {
+                mongodb::bson::from_slice((*options).as_bytes())?
+            } else {
+                Default::default()
+            };
+            if let Some(criteria) = resolve_selection_criteria(read_pref, options)? {
+                opts.selection_criteria = Some(criteria);
+            }
+            let result = (*collection).find((*filter).to_document()?, opts)?;
+            Ok(mongoc_cursor_t::new(result, (*collection).error_api))
+        })();
 
-    match result {
-        Ok(r) => r,
-        Err(_e) => ptr::null_mut(),
-    }
+        let cursor =
+            result.unwrap_or_else(|e| mongoc_cursor_t::with_error(&e, (*collection).error_api));
+        Box::into_raw(Box::new(cursor))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_find(
+    collection: *const mongoc_collection_t,
+    flags: mongoc_query_flags_t,
+    skip: u32,
+    limit: u32,
+    batch_size: u32,
+    query: *const bson_t,
+    fields: *const bson_t,
+    read_prefs: *const mongoc_read_prefs_t,
+) -> *mut mongoc_cursor_t {
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| {
+            let mut options = FindOptions::default();
+            let filter = unwrap_query_modifiers(&*query, &mut options)?;
+            apply_query_flags(flags, &mut options);
+            if let Some(criteria) = resolve_selection_criteria(read_prefs, std::ptr::null())? {
+                options.selection_criteria = Some(criteria);
+            }
+
+            if skip > 0 {
+                options.skip = Some(skip.into());
+            }
+            if limit > 0 {
+                options.limit = Some(limit.into());
+            }
+            if batch_size > 0 {
+                options.batch_size = Some(batch_size);
+            }
+            if !fields.is_null() {
+                options.projection = Some((*fields).to_document()?);
+            }
+
+            let result = (*collection).find(filter, options)?;
+            Ok(mongoc_cursor_t::new(result, (*collection).error_api))
+        })();
+
+        let cursor =
+            result.unwrap_or_else(|e| mongoc_cursor_t::with_error(&e, (*collection).error_api));
+        Box::into_raw(Box::new(cursor))
+    })
 }
 
 #[no_mangle]
@@ -152,27 +583,32 @@ pub unsafe extern "C" fn mongoc_collection_aggregate(
     _flags: mongoc_query_flags_t,
     pipeline: *const bson_t,
     options: *const bson_t,
-    _read_pref: *const mongoc_read_prefs_t,
+    read_pref: *const mongoc_read_prefs_t,
 ) -> *mut mongoc_cursor_t {
-    let result: anyhow::Result<_> = (|| {
-        let opts: AggregateOptions = if !options.is_null() {
-            mongodb::bson::from_slice((*options).as_bytes())?
-        } else {
-            Default::default()
-        };
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| {
+            let mut opts: AggregateOptions = if !options.is_null() {
+                mongodb::bson::from_slice((*options).as_bytes())?
+            } else {
+                Default::default()
+            };
+            if let Some(criteria) = resolve_selection_criteria(read_pref, options)? {
+                opts.selection_criteria = Some(criteria);
+            }
 
-        let pipeline = make_agg_pipeline(pipeline)?;
+            let pipeline = make_agg_pipeline(pipeline)?;
 
-        let result = (*collection).aggregate(pipeline, opts)?;
-        Ok(Box::into_raw(Box::new(mongoc_cursor_t::new(
-            result.with_type(),
-        ))))
-    })();
+            let result = (*collection).aggregate(pipeline, opts)?;
+            Ok(mongoc_cursor_t::new(
+                result.with_type(),
+                (*collection).error_api,
+            ))
+        })();
 
-    match result {
-        Ok(r) => r,
-        Err(_e) => ptr::null_mut(),
-    }
+        let cursor =
+            result.unwrap_or_else(|e| mongoc_cursor_t::with_error(&e, (*collection).error_api));
+        Box::into_raw(Box::new(cursor))
+    })
 }
 
 #[no_mangle]
@@ -181,7 +617,9 @@ pub unsafe extern "C" fn mongoc_collection_watch(
     pipeline: *const bson_t<'static>,
     opts: *const bson_t<'static>,
 ) -> *const mongoc_cursor_t {
-    panic!("driver doesnt have change streams yet")
+    catch_panic(std::ptr::null_mut(), || {
+        panic!("driver doesnt have change streams yet")
+    })
 }
 
 #[no_mangle]
@@ -189,52 +627,189 @@ pub unsafe extern "C" fn mongoc_collection_count_documents(
     collection: *const mongoc_collection_t,
     filter: *const bson_t,
     options: *const bson_t,
-    _read_pref: *const mongoc_read_prefs_t,
+    read_pref: *const mongoc_read_prefs_t,
     _reply: *mut bson_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> i64 {
-    let result: anyhow::Result<_> = (|| {
-        let opts: CountOptions = if !options.is_null() {
-            mongodb::bson::from_slice((*options).as_bytes())?
-        } else {
-            Default::default()
-        };
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let mut opts: CountOptions = if !options.is_null() {
+                mongodb::bson::from_slice((*options).as_bytes())?
+            } else {
+                Default::default()
+            };
+            if let Some(criteria) = resolve_selection_criteria(read_pref, options)? {
+                opts.selection_criteria = Some(criteria);
+            }
 
-        let filter = (*filter).to_document()?;
+            let filter = (*filter).to_document()?;
 
-        let result = (*collection).count_documents(filter, opts)?;
-        Ok(result)
-    })();
+            let result = (*collection).count_documents(filter, opts)?;
+            Ok(result)
+        })();
 
-    match result {
-        Ok(r) => r as i64,
-        Err(_e) => -1,
-    }
+        match result {
+            Ok(r) => r as i64,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_count(
+    collection: *mut mongoc_collection_t,
+    flags: mongoc_query_flags_t,
+    query: *const bson_t,
+    skip: i64,
+    limit: i64,
+    read_prefs: *const mongoc_read_prefs_t,
+    error: *mut bson_error_t,
+) -> i64 {
+    catch_panic(error, || {
+        mongoc_collection_count_with_opts(
+            collection,
+            flags,
+            query,
+            skip,
+            limit,
+            std::ptr::null(),
+            read_prefs,
+            error,
+        )
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_count_with_opts(
+    collection: *mut mongoc_collection_t,
+    flags: mongoc_query_flags_t,
+    query: *const bson_t,
+    skip: i64,
+    limit: i64,
+    opts: *const bson_t,
+    read_prefs: *const mongoc_read_prefs_t,
+    error: *mut bson_error_t,
+) -> i64 {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let mut options: CountOptions = match opts.as_ref() {
+                Some(opts) => mongodb::bson::from_slice(opts.as_bytes())?,
+                None => Default::default(),
+            };
+            options.max_time = max_time_ms(opts)?.or(options.max_time);
+            if skip > 0 {
+                options.skip = Some(skip as u64);
+            }
+            if limit > 0 {
+                options.limit = Some(limit as u64);
+            }
+            if flags.contains(mongoc_query_flags_t::MONGOC_QUERY_SECONDARY_OK) {
+                options.selection_criteria = Some(SelectionCriteria::ReadPreference(
+                    ReadPreference::SecondaryPreferred {
+                        options: Default::default(),
+                    },
+                ));
+            }
+            if let Some(criteria) = resolve_selection_criteria(read_prefs, opts)? {
+                options.selection_criteria = Some(criteria);
+            }
+
+            let filter = match query.as_ref() {
+                Some(query) => query.to_document()?,
+                None => Document::new(),
+            };
+            let session = mongoc_client_session_t::from_opts(opts)?;
+            let count = match session.as_mut() {
+                Some(s) => (*collection).count_documents_with_session(filter, options, s)?,
+                None => (*collection).count_documents(filter, options)?,
+            };
+            Ok(count)
+        })();
+
+        match result {
+            Ok(count) => count as i64,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_distinct_with_opts(
+    collection: *mut mongoc_collection_t,
+    field_name: *const c_char,
+    filter: *const bson_t,
+    opts: *const bson_t,
+    read_prefs: *const mongoc_read_prefs_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let field_name = CStr::from_ptr(field_name).to_str()?;
+            let mut options: DistinctOptions = match opts.as_ref() {
+                Some(opts) => mongodb::bson::from_slice(opts.as_bytes())?,
+                None => Default::default(),
+            };
+            options.max_time = max_time_ms(opts)?.or(options.max_time);
+            if let Some(criteria) = resolve_selection_criteria(read_prefs, opts)? {
+                options.selection_criteria = Some(criteria);
+            }
+
+            let filter = match filter.as_ref() {
+                Some(filter) => Some(filter.to_document()?),
+                None => None,
+            };
+            let session = mongoc_client_session_t::from_opts(opts)?;
+            let values = match session.as_mut() {
+                Some(s) => (*collection).distinct_with_session(field_name, filter, options, s)?,
+                None => (*collection).distinct(field_name, filter, options)?,
+            };
+
+            // The same shape as the server's reply to the distinct command.
+            Ok(doc! { "values": values, "ok": 1.0 })
+        })();
+
+        write_command_reply(collection, result, reply, error)
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_estimated_document_count(
     collection: *const mongoc_collection_t,
     options: *const bson_t,
-    _read_pref: *const mongoc_read_prefs_t,
+    read_pref: *const mongoc_read_prefs_t,
     _reply: *mut bson_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> i64 {
-    let result: anyhow::Result<_> = (|| {
-        let opts: EstimatedDocumentCountOptions = if !options.is_null() {
-            mongodb::bson::from_slice((*options).as_bytes())?
-        } else {
-            Default::default()
-        };
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let mut opts: EstimatedDocumentCountOptions = if !options.is_null() {
+                mongodb::bson::from_slice((*options).as_bytes())?
+            } else {
+                Default::default()
+            };
+            if let Some(criteria) = resolve_selection_criteria(read_pref, options)? {
+                opts.selection_criteria = Some(criteria);
+            }
 
-        let result = (*collection).estimated_document_count(opts)?;
-        Ok(result)
-    })();
+            let result = (*collection).estimated_document_count(opts)?;
+            Ok(result)
+        })();
 
-    match result {
-        Ok(r) => r as i64,
-        Err(_e) => -1,
-    }
+        match result {
+            Ok(r) => r as i64,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                -1
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -242,21 +817,117 @@ pub unsafe extern "C" fn mongoc_collection_find_indexes_with_opts(
     collection: *const mongoc_collection_t,
     options: *const bson_t,
 ) -> *mut mongoc_cursor_t {
-    let result: anyhow::Result<_> = (|| {
-        let opts: ListIndexesOptions = if !options.is_null() {
-            mongodb::bson::from_slice((*options).as_bytes())?
-        } else {
-            Default::default()
-        };
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| {
+            let opts: ListIndexesOptions = if !options.is_null() {
+                mongodb::bson::from_slice((*options).as_bytes())?
+            } else {
+                Default::default()
+            };
 
-        let result = (*collection).list_indexes(opts)?;
-        Ok(result)
-    })();
+            let result = (*collection).list_indexes(opts)?;
+            Ok(mongoc_cursor_t::new(
+                result.with_type(),
+                (*collection).error_api,
+            ))
+        })();
 
-    match result {
-        Ok(r) => Box::into_raw(Box::new(mongoc_cursor_t::new(r.with_type()))),
-        Err(_e) => std::ptr::null_mut(),
-    }
+        let cursor =
+            result.unwrap_or_else(|e| mongoc_cursor_t::with_error(&e, (*collection).error_api));
+        Box::into_raw(Box::new(cursor))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_create_indexes_with_opts(
+    collection: *mut mongoc_collection_t,
+    models: *const *mut mongoc_index_model_t,
+    n_models: usize,
+    opts: *const bson_t<'static>,
+    reply: *mut bson_t<'static>,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let indexes = std::slice::from_raw_parts(models, n_models)
+                .iter()
+                .map(|model| (**model).to_index_spec())
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let command = doc! { "createIndexes": (*collection).name(), "indexes": indexes };
+            (*collection).run_command_with_opts(command, opts)
+        })();
+
+        write_command_reply(collection, result, reply, error)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_create_index_with_opts(
+    collection: *mut mongoc_collection_t,
+    keys: *const bson_t<'static>,
+    index_opts: *const mongoc_index_opt_t,
+    opts: *const bson_t<'static>,
+    reply: *mut bson_t<'static>,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let index = match index_opts.as_ref() {
+                Some(index_opts) => index_opts.to_index_spec(&*keys)?,
+                None => mongoc_index_opt_t::default().to_index_spec(&*keys)?,
+            };
+            let command = doc! { "createIndexes": (*collection).name(), "indexes": [index] };
+            (*collection).run_command_with_opts(command, opts)
+        })();
+
+        write_command_reply(collection, result, reply, error)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_drop_index_with_opts(
+    collection: *mut mongoc_collection_t,
+    index_name: *const c_char,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let index_name = CStr::from_ptr(index_name).to_str()?;
+            let command = doc! { "dropIndexes": (*collection).name(), "index": index_name };
+            (*collection).run_command_with_opts(command, opts)
+        })();
+
+        write_command_reply(collection, result, std::ptr::null_mut(), error)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_drop_with_opts(
+    collection: *mut mongoc_collection_t,
+    opts: *const bson_t<'static>,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let command = doc! { "drop": (*collection).name() };
+        let result = (*collection).run_command_with_opts(command, opts);
+        write_command_reply(collection, result, std::ptr::null_mut(), error)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_keys_to_index_string(
+    keys: *const bson_t<'static>,
+) -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || {
+        let name = (*keys)
+            .to_document()
+            .and_then(|keys| index::keys_to_index_string(&keys));
+        match name.map(CString::new) {
+            Ok(Ok(name)) => name.into_raw(),
+            _ => std::ptr::null_mut(),
+        }
+    })
 }
 
 #[no_mangle]
@@ -266,62 +937,73 @@ pub unsafe extern "C" fn mongoc_collection_rename_with_opts(
     new_name: *const c_char,
     drop_target_before_rename: bool,
     options: *const bson_t,
-    _error: *const bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    let new_db = CStr::from_ptr(new_db).to_string_lossy();
-    let new_name = CStr::from_ptr(new_name).to_string_lossy();
+    catch_panic(error, || {
+        let new_db = CStr::from_ptr(new_db).to_string_lossy();
+        let new_name = CStr::from_ptr(new_name).to_string_lossy();
 
-    let result: anyhow::Result<_> = (|| {
-        let opts = if !options.is_null() {
-            (*options).to_document()?
-        } else {
-            Document::new()
-        };
+        let result: anyhow::Result<_> = (|| {
+            let opts = if !options.is_null() {
+                (*options).to_document()?
+            } else {
+                Document::new()
+            };
 
-        let mut cmd = doc! {
-            "renameCollection": format!("{}", (*collection).namespace()),
-            "to": format!("{}.{}", new_db, new_name),
-            "dropTarget": drop_target_before_rename
-        };
-        cmd.extend(opts);
+            let mut cmd = doc! {
+                "renameCollection": format!("{}", (*collection).namespace()),
+                "to": format!("{}.{}", new_db, new_name),
+                "dropTarget": drop_target_before_rename
+            };
+            cmd.extend(opts);
 
-        Ok((*collection).database().run_command(cmd, None)?)
-    })();
+            Ok((*collection).database().run_command(cmd, None)?)
+        })();
 
-    result.is_ok()
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_read_command_with_opts(
     collection: *mut mongoc_collection_t,
     command: *const bson_t<'static>,
-    _rp: *const mongoc_read_prefs_t,
+    rp: *const mongoc_read_prefs_t,
     opts: *const bson_t<'static>,
     reply: *mut bson_t<'static>,
-    _error: *const bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    let result: anyhow::Result<_> = (|| {
-        let opts = if !opts.is_null() {
-            (*opts).to_document()?
-        } else {
-            Document::new()
-        };
-
-        let mut cmd = (*command).to_document()?;
-        cmd.extend(opts);
-
-        let doc_reply = (*collection).database().run_command(cmd, None)?;
-        let raw_doc_reply = RawDocumentBuf::from_document(&doc_reply)?;
-        Ok(raw_doc_reply)
-    })();
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let selection_criteria = resolve_selection_criteria(rp, opts)?
+                .unwrap_or_else(|| (*collection).read_prefs.selection_criteria());
+            let doc_reply = database::run_command_with_opts(
+                &(*collection).database(),
+                (*command).to_document()?,
+                Some(selection_criteria),
+                opts,
+            )?;
+            let raw_doc_reply = RawDocumentBuf::from_document(&doc_reply)?;
+            Ok(raw_doc_reply)
+        })();
 
-    match result {
-        Ok(r) => {
-            *reply = r.into();
-            true
+        match result {
+            Ok(r) => {
+                *reply = r.into();
+                true
+            }
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
         }
-        Err(_) => false,
-    }
+    })
 }
 
 #[no_mangle]
@@ -330,16 +1012,28 @@ pub unsafe extern "C" fn mongoc_collection_write_command_with_opts(
     command: *const bson_t<'static>,
     opts: *const bson_t<'static>,
     reply: *mut bson_t<'static>,
-    error: *const bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    mongoc_collection_read_command_with_opts(
-        collection,
-        command,
-        std::ptr::null(),
-        opts,
-        reply,
-        error,
-    )
+    catch_panic(error, || {
+        // Writes always go to the primary, whatever the collection's read preferences.
+        let result: anyhow::Result<_> = (|| {
+            let reply = (*collection).run_command_with_opts((*command).to_document()?, opts)?;
+            Ok(RawDocumentBuf::from_document(&reply)?)
+        })();
+
+        match result {
+            Ok(r) => {
+                if !reply.is_null() {
+                    *reply = r.into();
+                }
+                true
+            }
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -347,9 +1041,13 @@ pub unsafe extern "C" fn mongoc_collection_create_bulk_operation_with_opts(
     collection: *mut mongoc_collection_t,
     opts: *const bson_t<'static>,
 ) -> *mut mongoc_bulk_operation_t {
-    Box::into_raw(Box::new(mongoc_bulk_operation_t::new(
-        (*collection).clone(),
-    )))
+    catch_panic(std::ptr::null_mut(), || {
+        let mut bulk = mongoc_bulk_operation_t::for_collection(&*collection);
+        if !opts.is_null() {
+            bulk.apply_opts(&*opts);
+        }
+        Box::into_raw(Box::new(bulk))
+    })
 }
 
 #[no_mangle]
@@ -360,10 +1058,281 @@ pub unsafe extern "C" fn mongoc_collection_find_and_modify_with_opts(
     reply: *mut bson_t<'static>,
     error: *mut bson_error_t,
 ) -> bool {
-    todo!()
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let filter = (*query).to_document()?;
+            (*opts).execute(&(*collection), filter)
+        })();
+
+        match result {
+            Ok(r) => {
+                if !reply.is_null() {
+                    *reply = r.into();
+                }
+                true
+            }
+            Err(e) => {
+                set_error(error, (*collection).error_api, &e);
+                false
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_collection_find_and_modify(
+    collection: *mut mongoc_collection_t,
+    query: *const bson_t<'static>,
+    sort: *const bson_t<'static>,
+    update: *const bson_t<'static>,
+    fields: *const bson_t<'static>,
+    remove: bool,
+    upsert: bool,
+    new: bool,
+    reply: *mut bson_t<'static>,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        let mut flags = mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_NONE;
+        flags.set(
+            mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_REMOVE,
+            remove,
+        );
+        flags.set(
+            mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_UPSERT,
+            upsert,
+        );
+        flags.set(
+            mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_RETURN_NEW,
+            new,
+        );
+
+        let opts = mongoc_find_and_modify_opts_new();
+        mongoc_find_and_modify_opts_set_flags(opts, flags);
+        let ok = mongoc_find_and_modify_opts_set_sort(opts, sort)
+            && mongoc_find_and_modify_opts_set_update(opts, update)
+            && mongoc_find_and_modify_opts_set_fields(opts, fields)
+            && mongoc_collection_find_and_modify_with_opts(collection, query, opts, reply, error);
+        mongoc_find_and_modify_opts_destroy(opts);
+        ok
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_collection_destroy(collection: *mut mongoc_collection_t) {
-    drop(Box::from_raw(collection));
+    catch_panic(std::ptr::null_mut(), || {
+        drop(Box::from_raw(collection));
+    })
+}
+
+/// Runs one of the CRUD write helpers in the session named by `opts`, if any, and writes its reply.
+/// On failure the reply holds whatever write errors the server reported, as libmongoc's does.
+unsafe fn run_write(
+    collection: *const mongoc_collection_t,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+    f: impl FnOnce(Option<&mut ClientSession>) -> anyhow::Result<Document>,
+) -> bool {
+    let result: anyhow::Result<_> = (|| {
+        let session = mongoc_client_session_t::from_opts(opts)?;
+        let result = f(session.as_mut().map(|s| &mut **s))?;
+        Ok(RawDocumentBuf::from_document(&result)?)
+    })();
+
+    match result {
+        Ok(r) => {
+            if !reply.is_null() {
+                *reply = r.into();
+            }
+            true
+        }
+        Err(e) => {
+            if !reply.is_null() {
+                *reply = RawDocumentBuf::from_document(&write_error_reply(&e))
+                    .unwrap_or_default()
+                    .into();
+            }
+            set_error(error, (*collection).error_api, &e);
+            false
+        }
+    }
+}
+
+fn update_reply(result: UpdateResult) -> Document {
+    let mut reply = doc! {
+        "matchedCount": result.matched_count as i32,
+        "modifiedCount": result.modified_count as i32,
+        "upsertedCount": result.upserted_id.is_some() as i32,
+    };
+    if let Some(id) = result.upserted_id {
+        reply.insert("upsertedId", id);
+    }
+    reply
+}
+
+/// The writeErrors and writeConcernErrors libmongoc includes in the reply of a failed write.
+fn write_error_reply(error: &anyhow::Error) -> Document {
+    let mut reply = Document::new();
+    let kind = match error.downcast_ref::<mongodb::error::Error>() {
+        Some(e) => e.kind.as_ref(),
+        None => return reply,
+    };
+
+    let (write_errors, write_concern_error) = match kind {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => (
+            vec![doc! { "index": 0, "code": e.code, "errmsg": &e.message }],
+            None,
+        ),
+        ErrorKind::Write(WriteFailure::WriteConcernError(e)) => (Vec::new(), Some(e)),
+        ErrorKind::BulkWrite(failure) => (
+            failure
+                .write_errors
+                .iter()
+                .flatten()
+                .map(|e| doc! { "index": e.index as i32, "code": e.code, "errmsg": &e.message })
+                .collect(),
+            failure.write_concern_error.as_ref(),
+        ),
+        _ => return reply,
+    };
+
+    reply.insert("writeErrors", write_errors);
+    if let Some(e) = write_concern_error {
+        reply.insert(
+            "writeConcernErrors",
+            vec![doc! { "code": e.code, "errmsg": &e.message }],
+        );
+    }
+    reply
+}
+
+/// Parses the update document given to an update helper, which must be made of update operators
+/// or be an aggregation pipeline.
+fn update_modifications(update: &RawDocument) -> anyhow::Result<UpdateModifications> {
+    match update.into_iter().next().transpose()? {
+        Some(("0", _)) => {
+            let pipeline = update
+                .into_iter()
+                .map(|kvp| match kvp?.1 {
+                    RawBsonRef::Document(d) => Ok(d.try_into()?),
+                    o => anyhow::bail!("expected document in update pipeline, got {:?}", o),
+                })
+                .collect::<anyhow::Result<Vec<Document>>>()?;
+            Ok(UpdateModifications::Pipeline(pipeline))
+        }
+        Some((key, _)) if !key.starts_with('$') => anyhow::bail!(
+            "Invalid key '{}': update only works with $ operators and pipelines",
+            key
+        ),
+        _ => Ok(UpdateModifications::Document(update.try_into()?)),
+    }
+}
+
+/// Splits a legacy query of the form `{$query: ..., $orderby: ...}` into its filter and the find
+/// options its modifiers correspond to. A query without `$query` is used as the filter as-is.
+fn unwrap_query_modifiers(
+    query: &RawDocument,
+    options: &mut FindOptions,
+) -> anyhow::Result<Document> {
+    if query.get("$query")?.is_none() {
+        return Ok(query.try_into()?);
+    }
+
+    let mut filter = Document::new();
+    for kvp in query {
+        let (key, value) = kvp?;
+        match (key, value) {
+            ("$query", RawBsonRef::Document(d)) => filter = d.try_into()?,
+            ("$orderby", RawBsonRef::Document(d)) => options.sort = Some(d.try_into()?),
+            ("$hint", RawBsonRef::Document(d)) => options.hint = Some(Hint::Keys(d.try_into()?)),
+            ("$hint", RawBsonRef::String(s)) => options.hint = Some(Hint::Name(s.to_string())),
+            ("$comment", RawBsonRef::String(s)) => options.comment = Some(s.to_string()),
+            ("$maxTimeMS", RawBsonRef::Int32(ms)) if ms >= 0 => {
+                options.max_time = Some(Duration::from_millis(ms as u64))
+            }
+            ("$maxTimeMS", RawBsonRef::Int64(ms)) if ms >= 0 => {
+                options.max_time = Some(Duration::from_millis(ms as u64))
+            }
+            ("$max", RawBsonRef::Document(d)) => options.max = Some(d.try_into()?),
+            ("$min", RawBsonRef::Document(d)) => options.min = Some(d.try_into()?),
+            ("$returnKey", RawBsonRef::Boolean(b)) => options.return_key = Some(b),
+            ("$showDiskLoc", RawBsonRef::Boolean(b)) => options.show_record_id = Some(b),
+            (key, value) => anyhow::bail!(
+                "Invalid query modifier \"{}\" of type {:?}",
+                key,
+                value.element_type()
+            ),
+        }
+    }
+    Ok(filter)
+}
+
+/// Translates the legacy OP_QUERY flags into their find command options.
+fn apply_query_flags(flags: mongoc_query_flags_t, options: &mut FindOptions) {
+    if flags.contains(mongoc_query_flags_t::MONGOC_QUERY_TAILABLE_CURSOR) {
+        options.cursor_type = Some(
+            if flags.contains(mongoc_query_flags_t::MONGOC_QUERY_AWAIT_DATA) {
+                CursorType::TailableAwait
+            } else {
+                CursorType::Tailable
+            },
+        );
+    }
+    if flags.contains(mongoc_query_flags_t::MONGOC_QUERY_SECONDARY_OK) {
+        options.selection_criteria = Some(SelectionCriteria::ReadPreference(
+            ReadPreference::SecondaryPreferred {
+                options: Default::default(),
+            },
+        ));
+    }
+    if flags.contains(mongoc_query_flags_t::MONGOC_QUERY_NO_CURSOR_TIMEOUT) {
+        options.no_cursor_timeout = Some(true);
+    }
+    if flags.contains(mongoc_query_flags_t::MONGOC_QUERY_PARTIAL) {
+        options.allow_partial_results = Some(true);
+    }
+    // MONGOC_QUERY_OPLOG_REPLAY is ignored by servers since 4.4 and the driver doesn't send it,
+    // and the find command has no exhaust mode, so MONGOC_QUERY_EXHAUST results in a regular
+    // cursor.
+}
+
+/// Writes the reply of a command run by one of the collection helpers, or sets `error` if it
+/// failed. `reply` may be null.
+unsafe fn write_command_reply(
+    collection: *const mongoc_collection_t,
+    result: anyhow::Result<Document>,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    match result.and_then(|r| Ok(RawDocumentBuf::from_document(&r)?)) {
+        Ok(r) => {
+            if !reply.is_null() {
+                *reply = r.into();
+            }
+            true
+        }
+        Err(e) => {
+            if !reply.is_null() {
+                *reply = RawDocumentBuf::new().into();
+            }
+            set_error(error, (*collection).error_api, &e);
+            false
+        }
+    }
+}
+
+/// Reads "maxTimeMS" from `opts`, which the driver's options don't deserialize from that name.
+unsafe fn max_time_ms(opts: *const bson_t) -> anyhow::Result<Option<Duration>> {
+    let opts = match opts.as_ref() {
+        Some(opts) => opts,
+        None => return Ok(None),
+    };
+
+    match opts.get("maxTimeMS")? {
+        Some(RawBsonRef::Int32(ms)) if ms >= 0 => Ok(Some(Duration::from_millis(ms as u64))),
+        Some(RawBsonRef::Int64(ms)) if ms >= 0 => Ok(Some(Duration::from_millis(ms as u64))),
+        Some(other) => anyhow::bail!("Invalid maxTimeMS of type {:?}", other.element_type()),
+        None => Ok(None),
+    }
 }
@@ -1,34 +1,36 @@
-use std::ops::{Deref, DerefMut};
-
 use mongodb::{bson::RawDocumentBuf, sync::Cursor};
 
 use crate::{
-    bson::{bson_error_t, bson_new, bson_t},
+    bson::{bson_error_t, bson_t},
     client::mongoc_client_t,
+    error::{catch_panic, MongocError},
 };
 
 pub struct mongoc_cursor_t {
-    rust_cursor: Cursor<RawDocumentBuf>,
-}
-
-impl Deref for mongoc_cursor_t {
-    type Target = Cursor<RawDocumentBuf>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.rust_cursor
-    }
+    rust_cursor: Option<Cursor<RawDocumentBuf>>,
+    error_api: i32,
+    error: Option<MongocError>,
+    error_reply: bson_t<'static>,
 }
 
-impl DerefMut for mongoc_cursor_t {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.rust_cursor
+impl mongoc_cursor_t {
+    pub(crate) fn new(cursor: Cursor<RawDocumentBuf>, error_api: i32) -> Self {
+        Self {
+            rust_cursor: Some(cursor),
+            error_api,
+            error: None,
+            error_reply: RawDocumentBuf::new().into(),
+        }
     }
-}
 
-impl mongoc_cursor_t {
-    pub(crate) fn new(cursor: Cursor<RawDocumentBuf>) -> Self {
+    /// Creates a cursor that failed before it could be opened. Like libmongoc, functions that
+    /// return cursors report errors through the cursor rather than by returning NULL.
+    pub(crate) fn with_error(error: &anyhow::Error, error_api: i32) -> Self {
         Self {
-            rust_cursor: cursor,
+            rust_cursor: None,
+            error_api,
+            error: Some(MongocError::from_anyhow(error, error_api)),
+            error_reply: RawDocumentBuf::new().into(),
         }
     }
 }
@@ -39,7 +41,7 @@ pub unsafe extern "C" fn mongoc_cursor_new_from_command_reply_with_opts(
     _reply: *mut bson_t<'static>,
     _opts: *const bson_t<'static>,
 ) -> *mut mongoc_cursor_t {
-    panic!("cant implement")
+    catch_panic(std::ptr::null_mut(), || panic!("cant implement"))
 }
 
 #[no_mangle]
@@ -47,41 +49,80 @@ pub unsafe extern "C" fn mongoc_cursor_next(
     cursor: *mut mongoc_cursor_t,
     bson: *mut *const bson_t,
 ) -> bool {
-    let result: anyhow::Result<_> = (|| {
-        let result = (*cursor).next().transpose()?;
-        Ok(result)
-    })();
+    catch_panic(std::ptr::null_mut(), || {
+        if (*cursor).error.is_some() {
+            return false;
+        }
+
+        let rust_cursor = match (*cursor).rust_cursor {
+            Some(ref mut c) => c,
+            None => return false,
+        };
 
-    match result {
-        Ok(Some(doc)) => {
-            *bson = Box::into_raw(Box::new(doc.into()));
-            true
+        let result: anyhow::Result<_> = (|| {
+            let result = rust_cursor.next().transpose()?;
+            Ok(result)
+        })();
+
+        match result {
+            Ok(Some(doc)) => {
+                *bson = Box::into_raw(Box::new(doc.into()));
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                (*cursor).error = Some(MongocError::from_anyhow(&e, (*cursor).error_api));
+                false
+            }
         }
-        _ => false,
-    }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_cursor_more(_cursor: *mut mongoc_cursor_t) -> bool {
-    true
+    catch_panic(std::ptr::null_mut(), || true)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_cursor_get_id(cursor: *mut mongoc_cursor_t) -> i64 {
-    (*cursor).id()
+    catch_panic(std::ptr::null_mut(), || match (*cursor).rust_cursor {
+        Some(ref c) => c.id(),
+        None => 0,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_cursor_error(
+    cursor: *mut mongoc_cursor_t,
+    error: *mut bson_error_t,
+) -> bool {
+    catch_panic(error, || {
+        mongoc_cursor_error_document(cursor, error, std::ptr::null_mut())
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_cursor_error_document(
-    _cursor: *mut mongoc_cursor_t,
-    _error: *mut bson_error_t,
-    _reply: *const *mut bson_t<'static>,
+    cursor: *mut mongoc_cursor_t,
+    error: *mut bson_error_t,
+    reply: *mut *const bson_t<'static>,
 ) -> bool {
-    // TODO: implement this
-    false
+    catch_panic(error, || {
+        if !reply.is_null() {
+            *reply = &(*cursor).error_reply;
+        }
+
+        match (*cursor).error {
+            Some(ref e) => {
+                e.write_to(error);
+                true
+            }
+            None => false,
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_cursor_destroy(cursor: *mut mongoc_cursor_t) {
-    drop(Box::from_raw(cursor))
+    catch_panic(std::ptr::null_mut(), || drop(Box::from_raw(cursor)))
 }
@@ -1,8 +1,8 @@
 use std::{ffi::CStr, ops::Deref, os::raw::c_char};
 
 use mongodb::{
-    bson::RawDocumentBuf,
-    options::{AggregateOptions, CreateCollectionOptions},
+    bson::{Bson, Document, RawDocumentBuf},
+    options::{AggregateOptions, CreateCollectionOptions, DatabaseOptions, SelectionCriteria},
     sync::{Client, Database},
 };
 
@@ -11,20 +11,111 @@ use crate::{
     client::{make_agg_pipeline, mongoc_client_t},
     collection::mongoc_collection_t,
     cursor::mongoc_cursor_t,
+    error::{catch_panic, set_error},
     read_concern::mongoc_read_concern_t,
-    read_pref::mongoc_read_prefs_t,
+    read_pref::{mongoc_read_prefs_t, resolve_selection_criteria},
+    session::mongoc_client_session_t,
     write_concern::mongoc_write_concern_t,
 };
 
 #[allow(non_camel_case_types)]
 pub struct mongoc_database_t {
     database: Database,
+    pub(crate) client: Client,
+    pub(crate) error_api: i32,
+    pub(crate) read_concern: mongoc_read_concern_t,
+    pub(crate) write_concern: mongoc_write_concern_t,
+    pub(crate) read_prefs: mongoc_read_prefs_t,
 }
 
 impl mongoc_database_t {
-    pub(crate) unsafe fn new(client: &Client, name: impl AsRef<str>) -> mongoc_database_t {
-        mongoc_database_t {
-            database: (*client).database(name.as_ref()),
+    /// Creates a handle for the database `name`, inheriting the client's read concern, write
+    /// concern and read preferences.
+    pub(crate) fn new(client: &mongoc_client_t, name: impl AsRef<str>) -> mongoc_database_t {
+        let mut database = mongoc_database_t {
+            database: client.database(name.as_ref()),
+            client: Client::clone(client),
+            error_api: client.error_api,
+            read_concern: client.read_concern.clone(),
+            write_concern: client.write_concern.clone(),
+            read_prefs: client.read_prefs.clone(),
+        };
+        database.apply_options();
+        database
+    }
+
+    /// Rebuilds the underlying database so it uses the handle's current read concern, write
+    /// concern and read preferences.
+    fn apply_options(&mut self) {
+        let mut options = DatabaseOptions::default();
+        options.selection_criteria = Some(self.read_prefs.selection_criteria());
+        options.read_concern = self.read_concern.rust_read_concern().cloned();
+        options.write_concern = self.write_concern.rust_write_concern().cloned();
+
+        let name = self.database.name().to_string();
+        self.database = self.client.database_with_options(&name, options);
+    }
+}
+
+/// Runs `command` against `database` with `opts` appended, on a server matching
+/// `selection_criteria` and in the session named by the opts' "sessionId", if any.
+pub(crate) unsafe fn run_command_with_opts(
+    database: &Database,
+    mut command: Document,
+    selection_criteria: Option<SelectionCriteria>,
+    opts: *const bson_t,
+) -> anyhow::Result<Document> {
+    let session = mongoc_client_session_t::from_opts(opts)?;
+    if let Some(opts) = opts.as_ref() {
+        for kvp in opts.deref() {
+            let (key, value) = kvp?;
+            if key != "sessionId" && key != "readPreference" {
+                command.insert(key, Bson::try_from(value.to_raw_bson())?);
+            }
+        }
+    }
+
+    let reply = match session.as_mut() {
+        Some(session) => database.run_command_with_session(command, selection_criteria, session)?,
+        None => database.run_command(command, selection_criteria)?,
+    };
+    Ok(reply)
+}
+
+/// Runs a database command for one of the command helpers and writes its reply. The read
+/// preference is `read_prefs`, else the opts' "readPreference", else `default_prefs`; commands
+/// with no default run on the primary.
+unsafe fn command_with_opts(
+    database: *const mongoc_database_t,
+    command: *const bson_t,
+    read_prefs: *const mongoc_read_prefs_t,
+    default_prefs: Option<&mongoc_read_prefs_t>,
+    opts: *const bson_t,
+    reply: *mut bson_t,
+    error: *mut bson_error_t,
+) -> bool {
+    let result: anyhow::Result<_> = (|| {
+        let selection_criteria = resolve_selection_criteria(read_prefs, opts)?
+            .or_else(|| default_prefs.map(|rp| rp.selection_criteria()));
+        let reply = run_command_with_opts(
+            &(*database).database,
+            (*command).to_document()?,
+            selection_criteria,
+            opts,
+        )?;
+        Ok(RawDocumentBuf::from_document(&reply)?)
+    })();
+
+    match result {
+        Ok(r) => {
+            if !reply.is_null() {
+                *reply = r.into();
+            }
+            true
+        }
+        Err(e) => {
+            set_error(error, (*database).error_api, &e);
+            false
         }
     }
 }
@@ -39,107 +130,107 @@ impl Deref for mongoc_database_t {
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_set_read_concern(
-    database: *const mongoc_database_t,
+    database: *mut mongoc_database_t,
     read_concern: *const mongoc_read_concern_t,
 ) -> bool {
-    panic!("cant do this in rust")
+    catch_panic(std::ptr::null_mut(), || {
+        (*database).read_concern = read_concern.as_ref().cloned().unwrap_or_default();
+        (*database).apply_options();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_get_read_concern(
     database: *const mongoc_database_t,
 ) -> *const mongoc_read_concern_t {
-    panic!("cant do this in rust")
+    catch_panic(std::ptr::null_mut(), || {
+        &(*database).read_concern as *const _
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_set_write_concern(
-    database: *const mongoc_database_t,
-    read_concern: *const mongoc_write_concern_t,
+    database: *mut mongoc_database_t,
+    write_concern: *const mongoc_write_concern_t,
 ) -> bool {
-    panic!("cant do this in rust")
+    catch_panic(std::ptr::null_mut(), || {
+        (*database).write_concern = write_concern.as_ref().cloned().unwrap_or_default();
+        (*database).apply_options();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_get_write_concern(
     database: *const mongoc_database_t,
 ) -> *const mongoc_write_concern_t {
-    panic!("cant do this in rust")
+    catch_panic(std::ptr::null_mut(), || {
+        &(*database).write_concern as *const _
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_set_read_prefs(
-    database: *const mongoc_database_t,
-    read_concern: *const mongoc_read_prefs_t,
+    database: *mut mongoc_database_t,
+    read_prefs: *const mongoc_read_prefs_t,
 ) -> bool {
-    panic!("cant do this in rust")
+    catch_panic(std::ptr::null_mut(), || {
+        (*database).read_prefs = read_prefs.as_ref().cloned().unwrap_or_default();
+        (*database).apply_options();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_get_read_prefs(
     database: *const mongoc_database_t,
 ) -> *const mongoc_read_prefs_t {
-    panic!("cant do this in rust")
+    catch_panic(std::ptr::null_mut(), || &(*database).read_prefs as *const _)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_command_simple(
     database: *const mongoc_database_t,
     command: *const bson_t,
-    _read_pref: *const u8,
+    read_pref: *const mongoc_read_prefs_t,
     reply: *mut bson_t,
-    _error: *const u8,
+    error: *mut bson_error_t,
 ) -> bool {
-    let result: anyhow::Result<_> = (|| {
-        let reply = (*database)
-            .database
-            .run_command((*command).to_document()?, None)?;
-        Ok(RawDocumentBuf::from_document(&reply)?)
-    })();
+    catch_panic(error, || {
+        let result: anyhow::Result<_> = (|| {
+            let selection_criteria = read_pref.as_ref().map(|rp| rp.selection_criteria());
+            let reply = (*database)
+                .database
+                .run_command((*command).to_document()?, selection_criteria)?;
+            Ok(RawDocumentBuf::from_document(&reply)?)
+        })();
 
-    match result {
-        Ok(r) => {
-            *reply = r.into();
-            true
-        }
-        Err(e) => {
-            // TODO: set error here
-            false
+        match result {
+            Ok(r) => {
+                *reply = r.into();
+                true
+            }
+            Err(e) => {
+                set_error(error, (*database).error_api, &e);
+                false
+            }
         }
-    }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_command_with_opts(
     database: *const mongoc_database_t,
     command: *const bson_t,
-    _read_pref: *const mongoc_read_prefs_t,
+    read_pref: *const mongoc_read_prefs_t,
     opts: *const bson_t,
     reply: *mut bson_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    let result: anyhow::Result<_> = (|| {
-        println!("building command");
-        let mut command = (*command).to_document()?;
-        command.extend((*opts).to_document()?);
-        println!("got command: {}", command);
-        let reply = (*database).database.run_command(command, None)?;
-        println!("got reply: {}", reply);
-        Ok(RawDocumentBuf::from_document(&reply)?)
-    })();
-
-    match result {
-        Ok(r) => {
-            println!("assigning reply");
-            *reply = r.into();
-            println!("done");
-            true
-        }
-        Err(_e) => {
-            // TODO: set error here
-            false
-        }
-    }
+    catch_panic(error, || {
+        command_with_opts(database, command, read_pref, None, opts, reply, error)
+    })
 }
 
 #[no_mangle]
@@ -151,7 +242,18 @@ pub unsafe extern "C" fn mongoc_database_read_command_with_opts(
     reply: *mut bson_t,
     error: *mut bson_error_t,
 ) -> bool {
-    mongoc_database_command_with_opts(database, command, read_pref, opts, reply, error)
+    catch_panic(error, || {
+        let default_prefs = Some(&(*database).read_prefs);
+        command_with_opts(
+            database,
+            command,
+            read_pref,
+            default_prefs,
+            opts,
+            reply,
+            error,
+        )
+    })
 }
 
 #[no_mangle]
@@ -162,7 +264,17 @@ pub unsafe extern "C" fn mongoc_database_write_command_with_opts(
     reply: *mut bson_t,
     error: *mut bson_error_t,
 ) -> bool {
-    mongoc_database_command_with_opts(database, command, std::ptr::null(), opts, reply, error)
+    catch_panic(error, || {
+        command_with_opts(
+            database,
+            command,
+            std::ptr::null(),
+            None,
+            opts,
+            reply,
+            error,
+        )
+    })
 }
 
 #[no_mangle]
@@ -170,27 +282,32 @@ pub unsafe extern "C" fn mongoc_database_aggregate(
     database: *const mongoc_database_t,
     pipeline: *const bson_t,
     options: *const bson_t,
-    _read_pref: *const mongoc_read_prefs_t,
+    read_pref: *const mongoc_read_prefs_t,
 ) -> *const mongoc_cursor_t {
-    let result: anyhow::Result<_> = (|| {
-        let opts: AggregateOptions = if !options.is_null() {
-            mongodb::bson::from_slice((*options).as_bytes())?
-        } else {
-            Default::default()
-        };
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| {
+            let mut opts: AggregateOptions = if !options.is_null() {
+                mongodb::bson::from_slice((*options).as_bytes())?
+            } else {
+                Default::default()
+            };
+            if let Some(criteria) = resolve_selection_criteria(read_pref, options)? {
+                opts.selection_criteria = Some(criteria);
+            }
 
-        let pipeline = make_agg_pipeline(pipeline)?;
+            let pipeline = make_agg_pipeline(pipeline)?;
 
-        let result = (*database).aggregate(pipeline, opts)?;
-        Ok(Box::into_raw(Box::new(mongoc_cursor_t::new(
-            result.with_type(),
-        ))))
-    })();
+            let result = (*database).aggregate(pipeline, opts)?;
+            Ok(mongoc_cursor_t::new(
+                result.with_type(),
+                (*database).error_api,
+            ))
+        })();
 
-    match result {
-        Ok(r) => r,
-        Err(_e) => std::ptr::null_mut(),
-    }
+        let cursor =
+            result.unwrap_or_else(|e| mongoc_cursor_t::with_error(&e, (*database).error_api));
+        Box::into_raw(Box::new(cursor))
+    })
 }
 
 #[no_mangle]
@@ -199,7 +316,9 @@ pub unsafe extern "C" fn mongoc_database_watch(
     pipeline: *const bson_t<'static>,
     opts: *const bson_t<'static>,
 ) -> *const mongoc_cursor_t {
-    panic!("driver doesnt have change streams yet")
+    catch_panic(std::ptr::null_mut(), || {
+        panic!("driver doesnt have change streams yet")
+    })
 }
 
 #[no_mangle]
@@ -207,9 +326,11 @@ pub unsafe extern "C" fn mongoc_database_get_collection(
     database: *const mongoc_database_t,
     coll_name: *const c_char,
 ) -> *mut mongoc_collection_t {
-    let name = CStr::from_ptr(coll_name).to_string_lossy();
-    let coll = mongoc_collection_t::new((*database).deref(), name);
-    Box::into_raw(Box::new(coll))
+    catch_panic(std::ptr::null_mut(), || {
+        let name = CStr::from_ptr(coll_name).to_string_lossy();
+        let coll = mongoc_collection_t::new(&*database, name);
+        Box::into_raw(Box::new(coll))
+    })
 }
 
 #[no_mangle]
@@ -217,27 +338,34 @@ pub unsafe extern "C" fn mongoc_database_create_collection(
     database: *const mongoc_database_t,
     coll_name: *const c_char,
     opts: *const bson_t<'static>,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> *mut mongoc_collection_t {
-    let name = CStr::from_ptr(coll_name).to_string_lossy();
-    let result: anyhow::Result<_> = (|| {
-        let opts: CreateCollectionOptions = if !opts.is_null() {
-            mongodb::bson::from_slice((*opts).as_bytes())?
-        } else {
-            Default::default()
-        };
+    catch_panic(error, || {
+        let name = CStr::from_ptr(coll_name).to_string_lossy();
+        let result: anyhow::Result<_> = (|| {
+            let opts: CreateCollectionOptions = if !opts.is_null() {
+                mongodb::bson::from_slice((*opts).as_bytes())?
+            } else {
+                Default::default()
+            };
 
-        let result = (*database).create_collection(name, opts)?;
-        Ok(mongoc_database_get_collection(database, coll_name))
-    })();
+            let result = (*database).create_collection(name, opts)?;
+            Ok(mongoc_database_get_collection(database, coll_name))
+        })();
 
-    match result {
-        Ok(c) => c,
-        Err(_) => std::ptr::null_mut(),
-    }
+        match result {
+            Ok(c) => c,
+            Err(e) => {
+                set_error(error, (*database).error_api, &e);
+                std::ptr::null_mut()
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_database_destroy(database: *mut mongoc_database_t) {
-    drop(Box::from_raw(database));
+    catch_panic(std::ptr::null_mut(), || {
+        drop(Box::from_raw(database));
+    })
 }
@@ -1,106 +1,479 @@
-// #[repr(C)]
-// pub enum mongoc_error_domain_t {
-//    MONGOC_ERROR_CLIENT = 1,
-//    MONGOC_ERROR_STREAM,
-//    MONGOC_ERROR_PROTOCOL,
-//    MONGOC_ERROR_CURSOR,
-//    MONGOC_ERROR_QUERY,
-//    MONGOC_ERROR_INSERT,
-//    MONGOC_ERROR_SASL,
-//    MONGOC_ERROR_BSON,
-//    MONGOC_ERROR_MATCHER,
-//    MONGOC_ERROR_NAMESPACE,
-//    MONGOC_ERROR_COMMAND,
-//    MONGOC_ERROR_COLLECTION,
-//    MONGOC_ERROR_GRIDFS,
-//    MONGOC_ERROR_SCRAM,
-//    MONGOC_ERROR_SERVER_SELECTION,
-//    MONGOC_ERROR_WRITE_CONCERN,
-//    MONGOC_ERROR_SERVER, /* Error API Version 2 only */
-//    MONGOC_ERROR_TRANSACTION,
-//    MONGOC_ERROR_CLIENT_SIDE_ENCRYPTION, /* An error coming from libmongocrypt */
-//    MONGOC_ERROR_POOL
-// }
-
-// #[repr(C)]
-// pub enum mongoc_error_code_t {
-//    MONGOC_ERROR_STREAM_INVALID_TYPE = 1,
-//    MONGOC_ERROR_STREAM_INVALID_STATE,
-//    MONGOC_ERROR_STREAM_NAME_RESOLUTION,
-//    MONGOC_ERROR_STREAM_SOCKET,
-//    MONGOC_ERROR_STREAM_CONNECT,
-//    MONGOC_ERROR_STREAM_NOT_ESTABLISHED,
-
-//    MONGOC_ERROR_CLIENT_NOT_READY,
-//    MONGOC_ERROR_CLIENT_TOO_BIG,
-//    MONGOC_ERROR_CLIENT_TOO_SMALL,
-//    MONGOC_ERROR_CLIENT_GETNONCE,
-//    MONGOC_ERROR_CLIENT_AUTHENTICATE,
-//    MONGOC_ERROR_CLIENT_NO_ACCEPTABLE_PEER,
-//    MONGOC_ERROR_CLIENT_IN_EXHAUST,
-
-//    MONGOC_ERROR_PROTOCOL_INVALID_REPLY,
-//    MONGOC_ERROR_PROTOCOL_BAD_WIRE_VERSION,
-
-//    MONGOC_ERROR_CURSOR_INVALID_CURSOR,
-
-//    MONGOC_ERROR_QUERY_FAILURE,
-
-//    MONGOC_ERROR_BSON_INVALID,
-
-//    MONGOC_ERROR_MATCHER_INVALID,
-
-//    MONGOC_ERROR_NAMESPACE_INVALID,
-//    MONGOC_ERROR_NAMESPACE_INVALID_FILTER_TYPE,
-
-//    MONGOC_ERROR_COMMAND_INVALID_ARG,
-
-//    MONGOC_ERROR_COLLECTION_INSERT_FAILED,
-//    MONGOC_ERROR_COLLECTION_UPDATE_FAILED,
-//    MONGOC_ERROR_COLLECTION_DELETE_FAILED,
-//    MONGOC_ERROR_COLLECTION_DOES_NOT_EXIST = 26,
-
-//    MONGOC_ERROR_GRIDFS_INVALID_FILENAME,
-
-//    MONGOC_ERROR_SCRAM_NOT_DONE,
-//    MONGOC_ERROR_SCRAM_PROTOCOL_ERROR,
-
-//    MONGOC_ERROR_QUERY_COMMAND_NOT_FOUND = 59,
-//    MONGOC_ERROR_QUERY_NOT_TAILABLE = 13051,
-
-//    MONGOC_ERROR_SERVER_SELECTION_BAD_WIRE_VERSION,
-//    MONGOC_ERROR_SERVER_SELECTION_FAILURE,
-//    MONGOC_ERROR_SERVER_SELECTION_INVALID_ID,
-
-//    MONGOC_ERROR_GRIDFS_CHUNK_MISSING,
-//    MONGOC_ERROR_GRIDFS_PROTOCOL_ERROR,
-
-//    /* Dup with query failure. */
-//    MONGOC_ERROR_PROTOCOL_ERROR = 17,
-
-//    MONGOC_ERROR_WRITE_CONCERN_ERROR = 64,
-
-//    MONGOC_ERROR_DUPLICATE_KEY = 11000,
-
-//    MONGOC_ERROR_MAX_TIME_MS_EXPIRED = 50,
-
-//    MONGOC_ERROR_CHANGE_STREAM_NO_RESUME_TOKEN,
-//    MONGOC_ERROR_CLIENT_SESSION_FAILURE,
-//    MONGOC_ERROR_TRANSACTION_INVALID_STATE,
-//    MONGOC_ERROR_GRIDFS_CORRUPT,
-//    MONGOC_ERROR_GRIDFS_BUCKET_FILE_NOT_FOUND,
-//    MONGOC_ERROR_GRIDFS_BUCKET_STREAM,
-
-//    /* An error related to initializing client side encryption. */
-//    MONGOC_ERROR_CLIENT_INVALID_ENCRYPTION_STATE,
-
-//    MONGOC_ERROR_CLIENT_INVALID_ENCRYPTION_ARG,
-
-//    /* An error related to server version api */
-//    MONGOC_ERROR_CLIENT_API_ALREADY_SET,
-//    MONGOC_ERROR_CLIENT_API_FROM_POOL,
-//    MONGOC_ERROR_POOL_API_ALREADY_SET,
-//    MONGOC_ERROR_POOL_API_TOO_LATE,
-
-//    MONGOC_ERROR_CLIENT_INVALID_LOAD_BALANCER,
-// }
+use std::{
+    cell::{Cell, RefCell},
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+};
+
+use mongodb::error::{ErrorKind, WriteFailure};
+
+use crate::{bson::bson_error_t, MONGOC_ERROR_API_VERSION_2};
+
+// The C definitions of these enums live in mongoc-rust-error.h. They are mirrored here (rather
+// than exported through cbindgen) because the C enums contain duplicate discriminants, which Rust
+// enums don't allow.
+
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub(crate) enum mongoc_error_domain_t {
+    MONGOC_ERROR_CLIENT = 1,
+    MONGOC_ERROR_STREAM,
+    MONGOC_ERROR_PROTOCOL,
+    MONGOC_ERROR_CURSOR,
+    MONGOC_ERROR_QUERY,
+    MONGOC_ERROR_INSERT,
+    MONGOC_ERROR_SASL,
+    MONGOC_ERROR_BSON,
+    MONGOC_ERROR_MATCHER,
+    MONGOC_ERROR_NAMESPACE,
+    MONGOC_ERROR_COMMAND,
+    MONGOC_ERROR_COLLECTION,
+    MONGOC_ERROR_GRIDFS,
+    MONGOC_ERROR_SCRAM,
+    MONGOC_ERROR_SERVER_SELECTION,
+    MONGOC_ERROR_WRITE_CONCERN,
+    MONGOC_ERROR_SERVER,
+    MONGOC_ERROR_TRANSACTION,
+    MONGOC_ERROR_CLIENT_SIDE_ENCRYPTION,
+    MONGOC_ERROR_POOL,
+}
+
+#[allow(non_camel_case_types, dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub(crate) enum mongoc_error_code_t {
+    MONGOC_ERROR_STREAM_INVALID_TYPE = 1,
+    MONGOC_ERROR_STREAM_INVALID_STATE = 2,
+    MONGOC_ERROR_STREAM_NAME_RESOLUTION = 3,
+    MONGOC_ERROR_STREAM_SOCKET = 4,
+    MONGOC_ERROR_STREAM_CONNECT = 5,
+    MONGOC_ERROR_STREAM_NOT_ESTABLISHED = 6,
+
+    MONGOC_ERROR_CLIENT_NOT_READY = 7,
+    MONGOC_ERROR_CLIENT_TOO_BIG = 8,
+    MONGOC_ERROR_CLIENT_TOO_SMALL = 9,
+    MONGOC_ERROR_CLIENT_GETNONCE = 10,
+    MONGOC_ERROR_CLIENT_AUTHENTICATE = 11,
+    MONGOC_ERROR_CLIENT_NO_ACCEPTABLE_PEER = 12,
+    MONGOC_ERROR_CLIENT_IN_EXHAUST = 13,
+
+    MONGOC_ERROR_PROTOCOL_INVALID_REPLY = 14,
+    MONGOC_ERROR_PROTOCOL_BAD_WIRE_VERSION = 15,
+
+    MONGOC_ERROR_CURSOR_INVALID_CURSOR = 16,
+
+    MONGOC_ERROR_QUERY_FAILURE = 17,
+
+    MONGOC_ERROR_BSON_INVALID = 18,
+
+    MONGOC_ERROR_MATCHER_INVALID = 19,
+
+    MONGOC_ERROR_NAMESPACE_INVALID = 20,
+    MONGOC_ERROR_NAMESPACE_INVALID_FILTER_TYPE = 21,
+
+    MONGOC_ERROR_COMMAND_INVALID_ARG = 22,
+
+    MONGOC_ERROR_COLLECTION_INSERT_FAILED = 23,
+    MONGOC_ERROR_COLLECTION_UPDATE_FAILED = 24,
+    MONGOC_ERROR_COLLECTION_DELETE_FAILED = 25,
+    MONGOC_ERROR_COLLECTION_DOES_NOT_EXIST = 26,
+
+    MONGOC_ERROR_GRIDFS_INVALID_FILENAME = 27,
+
+    MONGOC_ERROR_SCRAM_NOT_DONE = 28,
+    MONGOC_ERROR_SCRAM_PROTOCOL_ERROR = 29,
+
+    MONGOC_ERROR_QUERY_COMMAND_NOT_FOUND = 59,
+    MONGOC_ERROR_QUERY_NOT_TAILABLE = 13051,
+
+    MONGOC_ERROR_SERVER_SELECTION_BAD_WIRE_VERSION = 13052,
+    MONGOC_ERROR_SERVER_SELECTION_FAILURE = 13053,
+    MONGOC_ERROR_SERVER_SELECTION_INVALID_ID = 13054,
+
+    MONGOC_ERROR_GRIDFS_CHUNK_MISSING = 13055,
+    MONGOC_ERROR_GRIDFS_PROTOCOL_ERROR = 13056,
+
+    MONGOC_ERROR_WRITE_CONCERN_ERROR = 64,
+
+    MONGOC_ERROR_DUPLICATE_KEY = 11000,
+
+    MONGOC_ERROR_MAX_TIME_MS_EXPIRED = 50,
+
+    MONGOC_ERROR_CHANGE_STREAM_NO_RESUME_TOKEN = 51,
+    MONGOC_ERROR_CLIENT_SESSION_FAILURE = 52,
+    MONGOC_ERROR_TRANSACTION_INVALID_STATE = 53,
+    MONGOC_ERROR_GRIDFS_CORRUPT = 54,
+    MONGOC_ERROR_GRIDFS_BUCKET_FILE_NOT_FOUND = 55,
+    MONGOC_ERROR_GRIDFS_BUCKET_STREAM = 56,
+
+    MONGOC_ERROR_CLIENT_INVALID_ENCRYPTION_STATE = 57,
+    MONGOC_ERROR_CLIENT_INVALID_ENCRYPTION_ARG = 58,
+
+    // MONGOC_ERROR_CLIENT_API_ALREADY_SET shares its value with
+    // MONGOC_ERROR_QUERY_COMMAND_NOT_FOUND, see below.
+    MONGOC_ERROR_CLIENT_API_FROM_POOL = 60,
+    MONGOC_ERROR_POOL_API_ALREADY_SET = 61,
+    MONGOC_ERROR_POOL_API_TOO_LATE = 62,
+
+    MONGOC_ERROR_CLIENT_INVALID_LOAD_BALANCER = 63,
+}
+
+#[allow(dead_code)]
+impl mongoc_error_code_t {
+    /* Dup with query failure. */
+    pub(crate) const MONGOC_ERROR_PROTOCOL_ERROR: Self = Self::MONGOC_ERROR_QUERY_FAILURE;
+
+    pub(crate) const MONGOC_ERROR_CLIENT_API_ALREADY_SET: Self =
+        Self::MONGOC_ERROR_QUERY_COMMAND_NOT_FOUND;
+}
+
+/// An error in the shape libmongoc reports it, ready to be copied into a `bson_error_t`.
+#[derive(Clone, Debug)]
+pub(crate) struct MongocError {
+    pub(crate) domain: u32,
+    pub(crate) code: u32,
+    pub(crate) message: String,
+}
+
+impl MongocError {
+    pub(crate) fn new(
+        domain: mongoc_error_domain_t,
+        code: mongoc_error_code_t,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            domain: domain as u32,
+            code: code as u32,
+            message: message.into(),
+        }
+    }
+
+    /// An error reported by the server. Server error codes are passed through as-is, with the
+    /// libmongoc fallback for responses that didn't include one.
+    pub(crate) fn server(
+        domain: mongoc_error_domain_t,
+        code: i32,
+        message: impl Into<String>,
+    ) -> Self {
+        let code = if code == 0 {
+            mongoc_error_code_t::MONGOC_ERROR_QUERY_FAILURE as u32
+        } else {
+            code as u32
+        };
+        Self {
+            domain: domain as u32,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Translates an error returned by the driver or by our own argument handling into its
+    /// libmongoc equivalent. `error_api` is the error API version of the handle the error came
+    /// from, which determines the domain server errors are reported in.
+    pub(crate) fn from_anyhow(error: &anyhow::Error, error_api: i32) -> Self {
+        use mongoc_error_code_t::*;
+        use mongoc_error_domain_t::*;
+
+        if let Some(e) = error.downcast_ref::<mongodb::error::Error>() {
+            return Self::from_driver(e, error_api);
+        }
+
+        if let Some(e) = error.downcast_ref::<MongocError>() {
+            return e.clone();
+        }
+
+        if error.is::<mongodb::bson::de::Error>()
+            || error.is::<mongodb::bson::ser::Error>()
+            || error.is::<mongodb::bson::raw::Error>()
+        {
+            return Self::new(
+                MONGOC_ERROR_BSON,
+                MONGOC_ERROR_BSON_INVALID,
+                error.to_string(),
+            );
+        }
+
+        Self::new(
+            MONGOC_ERROR_COMMAND,
+            MONGOC_ERROR_COMMAND_INVALID_ARG,
+            error.to_string(),
+        )
+    }
+
+    fn from_driver(error: &mongodb::error::Error, error_api: i32) -> Self {
+        use mongoc_error_code_t::*;
+        use mongoc_error_domain_t::*;
+
+        // Version 1 of the error API reports server errors in the domain of the operation that
+        // failed, version 2 always uses MONGOC_ERROR_SERVER.
+        let (command_domain, write_domain) = if error_api >= MONGOC_ERROR_API_VERSION_2 {
+            (MONGOC_ERROR_SERVER, MONGOC_ERROR_SERVER)
+        } else {
+            (MONGOC_ERROR_QUERY, MONGOC_ERROR_COMMAND)
+        };
+
+        let message = error.kind.to_string();
+        match error.kind.as_ref() {
+            ErrorKind::Command(e) => {
+                if error_api < MONGOC_ERROR_API_VERSION_2
+                    && e.code == MONGOC_ERROR_QUERY_COMMAND_NOT_FOUND as i32
+                {
+                    Self::new(
+                        MONGOC_ERROR_QUERY,
+                        MONGOC_ERROR_QUERY_COMMAND_NOT_FOUND,
+                        &e.message,
+                    )
+                } else {
+                    Self::server(command_domain, e.code, &e.message)
+                }
+            }
+            ErrorKind::Write(WriteFailure::WriteError(e)) => {
+                Self::server(write_domain, e.code, &e.message)
+            }
+            ErrorKind::Write(WriteFailure::WriteConcernError(e)) => {
+                Self::server(MONGOC_ERROR_WRITE_CONCERN, e.code, &e.message)
+            }
+            ErrorKind::BulkWrite(failure) => {
+                match (
+                    failure.write_errors.as_ref().and_then(|e| e.first()),
+                    failure.write_concern_error.as_ref(),
+                ) {
+                    (Some(e), _) => Self::server(write_domain, e.code, &e.message),
+                    (None, Some(e)) => Self::server(MONGOC_ERROR_WRITE_CONCERN, e.code, &e.message),
+                    (None, None) => {
+                        Self::new(MONGOC_ERROR_COMMAND, MONGOC_ERROR_QUERY_FAILURE, message)
+                    }
+                }
+            }
+            ErrorKind::ServerSelection { .. } => Self::new(
+                MONGOC_ERROR_SERVER_SELECTION,
+                MONGOC_ERROR_SERVER_SELECTION_FAILURE,
+                message,
+            ),
+            ErrorKind::InvalidArgument { .. } | ErrorKind::InvalidTlsConfig { .. } => Self::new(
+                MONGOC_ERROR_COMMAND,
+                MONGOC_ERROR_COMMAND_INVALID_ARG,
+                message,
+            ),
+            ErrorKind::Authentication { .. } => Self::new(
+                MONGOC_ERROR_CLIENT,
+                MONGOC_ERROR_CLIENT_AUTHENTICATE,
+                message,
+            ),
+            ErrorKind::DnsResolve { .. } => Self::new(
+                MONGOC_ERROR_STREAM,
+                MONGOC_ERROR_STREAM_NAME_RESOLUTION,
+                message,
+            ),
+            ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. } => {
+                Self::new(MONGOC_ERROR_STREAM, MONGOC_ERROR_STREAM_SOCKET, message)
+            }
+            ErrorKind::InvalidResponse { .. } => Self::new(
+                MONGOC_ERROR_PROTOCOL,
+                MONGOC_ERROR_PROTOCOL_INVALID_REPLY,
+                message,
+            ),
+            ErrorKind::IncompatibleServer { .. } => Self::new(
+                MONGOC_ERROR_PROTOCOL,
+                MONGOC_ERROR_PROTOCOL_BAD_WIRE_VERSION,
+                message,
+            ),
+            ErrorKind::BsonDeserialization(_) | ErrorKind::BsonSerialization(_) => {
+                Self::new(MONGOC_ERROR_BSON, MONGOC_ERROR_BSON_INVALID, message)
+            }
+            ErrorKind::Transaction { .. } => Self::new(
+                MONGOC_ERROR_TRANSACTION,
+                MONGOC_ERROR_TRANSACTION_INVALID_STATE,
+                message,
+            ),
+            ErrorKind::SessionsNotSupported => Self::new(
+                MONGOC_ERROR_CLIENT,
+                MONGOC_ERROR_CLIENT_SESSION_FAILURE,
+                message,
+            ),
+            _ => Self::new(MONGOC_ERROR_CLIENT, MONGOC_ERROR_CLIENT_NOT_READY, message),
+        }
+    }
+
+    /// Copies this error into `error`, which may be null if the caller doesn't care about errors.
+    pub(crate) unsafe fn write_to(&self, error: *mut bson_error_t) {
+        if error.is_null() {
+            return;
+        }
+
+        (*error).domain = self.domain;
+        (*error).code = self.code;
+
+        // Leave room for the NUL terminator, and don't split a multi-byte character.
+        let capacity = (*error).message.len() - 1;
+        let mut len = self.message.len().min(capacity);
+        while !self.message.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        for (dst, src) in (*error)
+            .message
+            .iter_mut()
+            .zip(&self.message.as_bytes()[..len])
+        {
+            *dst = *src as c_char;
+        }
+        (*error).message[len] = 0;
+    }
+}
+
+impl std::fmt::Display for MongocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for MongocError {}
+
+/// Translates `e` according to `error_api` and writes it into `error`, which may be null.
+pub(crate) unsafe fn set_error(error: *mut bson_error_t, error_api: i32, e: &anyhow::Error) {
+    MongocError::from_anyhow(e, error_api).write_to(error)
+}
+
+/// Logs that `function` was called but can't do what was asked, for a function with no
+/// `bson_error_t` to report it in. The function then returns its documented failure value.
+pub(crate) fn log_unsupported(function: &str, reason: &str) {
+    eprintln!("mongoc-rust: {} is not supported: {}", function, reason);
+}
+
+static ABORT_ON_INTERNAL_ERROR: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<String>> = RefCell::new(None);
+    /// How many catch_panic calls are running on this thread, so the panic hook only handles
+    /// panics that catch_panic will catch.
+    static CATCHING: Cell<u32> = Cell::new(0);
+}
+
+/// Opts into aborting the process when an internal error (a Rust panic) reaches the C API,
+/// rather than reporting it as a failure of the call. Intended for test builds.
+#[no_mangle]
+pub extern "C" fn mongoc_rust_set_abort_on_internal_error(abort: bool) {
+    ABORT_ON_INTERNAL_ERROR.store(abort, Ordering::SeqCst);
+}
+
+/// The value an exported function returns when it fails: NULL, false, -1, etc.
+pub(crate) trait FailureValue {
+    fn failure() -> Self;
+}
+
+impl FailureValue for () {
+    fn failure() -> Self {}
+}
+
+impl FailureValue for bool {
+    fn failure() -> Self {
+        false
+    }
+}
+
+impl FailureValue for i32 {
+    fn failure() -> Self {
+        -1
+    }
+}
+
+impl FailureValue for i64 {
+    fn failure() -> Self {
+        -1
+    }
+}
+
+impl FailureValue for u32 {
+    fn failure() -> Self {
+        0
+    }
+}
+
+impl FailureValue for usize {
+    fn failure() -> Self {
+        0
+    }
+}
+
+impl<T> FailureValue for *const T {
+    fn failure() -> Self {
+        std::ptr::null()
+    }
+}
+
+impl<T> FailureValue for *mut T {
+    fn failure() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+/// For functions whose failure value is an argument, such as the fallback of a getter.
+impl<T> FailureValue for Option<T> {
+    fn failure() -> Self {
+        None
+    }
+}
+
+/// Runs the body of an exported function, catching any panic before it unwinds into C. A caught
+/// panic is logged, written into `error` (which may be null) and turned into the function's
+/// failure value.
+pub(crate) unsafe fn catch_panic<T: FailureValue>(
+    error: *mut bson_error_t,
+    f: impl FnOnce() -> T,
+) -> T {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        // Record our own panics instead of printing them, so they're only logged once, below.
+        // Panics elsewhere in the host process still go to whatever hook it had installed.
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) == 0 {
+                return previous(info);
+            }
+
+            let location = info
+                .location()
+                .map(|l| format!(" at {}:{}", l.file(), l.line()))
+                .unwrap_or_default();
+            let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = info.payload().downcast_ref::<String>() {
+                s.clone()
+            } else {
+                "unknown panic".to_string()
+            };
+            LAST_PANIC.with(|p| *p.borrow_mut() = Some(format!("{}{}", message, location)));
+        }));
+    });
+
+    CATCHING.with(|c| c.set(c.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(c.get() - 1));
+
+    match result {
+        Ok(t) => t,
+        Err(_) => {
+            let message = LAST_PANIC
+                .with(|p| p.borrow_mut().take())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            eprintln!("mongoc-rust: internal error: {}", message);
+
+            if ABORT_ON_INTERNAL_ERROR.load(Ordering::SeqCst) {
+                std::process::abort();
+            }
+
+            MongocError::new(
+                mongoc_error_domain_t::MONGOC_ERROR_CLIENT,
+                mongoc_error_code_t::MONGOC_ERROR_CLIENT_NOT_READY,
+                format!("internal error: {}", message),
+            )
+            .write_to(error);
+            T::failure()
+        }
+    }
+}
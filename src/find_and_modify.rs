@@ -1,18 +1,206 @@
-use crate::bson::bson_t;
+use std::time::Duration;
 
-pub struct mongoc_find_and_modify_opts_t {}
+use bitflags::bitflags;
+use mongodb::{
+    bson::{Bson, Document, RawBson, RawBsonRef, RawDocument, RawDocumentBuf},
+    options::{
+        FindOneAndDeleteOptions, FindOneAndReplaceOptions, FindOneAndUpdateOptions, ReturnDocument,
+        UpdateModifications,
+    },
+};
 
-#[repr(C)]
-pub enum mongoc_find_and_modify_flags_t {
-    MONGOC_FIND_AND_MODIFY_NONE = 0,
-    MONGOC_FIND_AND_MODIFY_REMOVE = 1 << 0,
-    MONGOC_FIND_AND_MODIFY_UPSERT = 1 << 1,
-    MONGOC_FIND_AND_MODIFY_RETURN_NEW = 1 << 2,
+use crate::{
+    bson::bson_t, collection::mongoc_collection_t, error::catch_panic,
+    session::mongoc_client_session_t,
+};
+
+#[derive(Default)]
+pub struct mongoc_find_and_modify_opts_t {
+    sort: Option<Document>,
+    update: Option<RawDocumentBuf>,
+    fields: Option<Document>,
+    flags: mongoc_find_and_modify_flags_t,
+    bypass_document_validation: Option<bool>,
+    max_time_ms: Option<u32>,
+    extra: Document,
+}
+
+bitflags! {
+    #[allow(non_camel_case_types)]
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct mongoc_find_and_modify_flags_t: u32 {
+        const MONGOC_FIND_AND_MODIFY_NONE = 0;
+        const MONGOC_FIND_AND_MODIFY_REMOVE = 1 << 0;
+        const MONGOC_FIND_AND_MODIFY_UPSERT = 1 << 1;
+        const MONGOC_FIND_AND_MODIFY_RETURN_NEW = 1 << 2;
+    }
+}
+
+/// The update document given to findAndModify, which libmongoc treats as a replacement unless
+/// its first key is an update operator.
+enum Update {
+    Modifications(UpdateModifications),
+    Replacement(RawDocumentBuf),
+}
+
+impl Update {
+    fn parse(update: &RawDocument) -> anyhow::Result<Self> {
+        match update.into_iter().next().transpose()? {
+            // An array of stages, as produced by bson_append_array / BCON arrays.
+            Some(("0", _)) => {
+                let pipeline = update
+                    .into_iter()
+                    .map(|kvp| match kvp?.1 {
+                        RawBsonRef::Document(d) => Ok(d.try_into()?),
+                        o => anyhow::bail!("expected document in update pipeline, got {:?}", o),
+                    })
+                    .collect::<anyhow::Result<Vec<Document>>>()?;
+                Ok(Update::Modifications(UpdateModifications::Pipeline(
+                    pipeline,
+                )))
+            }
+            Some((key, _)) if key.starts_with('$') => Ok(Update::Modifications(
+                UpdateModifications::Document(update.try_into()?),
+            )),
+            _ => Ok(Update::Replacement(update.to_raw_document_buf())),
+        }
+    }
+}
+
+impl mongoc_find_and_modify_opts_t {
+    /// Runs findAndModify against `collection` through the matching driver helper, so it's retried
+    /// like any other retryable write, and builds a reply in the shape of the server's
+    /// findAndModify response. A "sessionId" among the appended options runs it in that session.
+    pub(crate) unsafe fn execute(
+        &self,
+        collection: &mongoc_collection_t,
+        filter: Document,
+    ) -> anyhow::Result<RawDocumentBuf> {
+        let return_document = if self
+            .flags
+            .contains(mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_RETURN_NEW)
+        {
+            ReturnDocument::After
+        } else {
+            ReturnDocument::Before
+        };
+        let upsert = self
+            .flags
+            .contains(mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_UPSERT);
+        let max_time = self.max_time_ms()?;
+
+        let opts: bson_t = RawDocumentBuf::from_document(&self.extra)?.into();
+        let session = mongoc_client_session_t::from_opts(&opts)?;
+        let session = session.as_mut().map(|s| &mut **s);
+
+        let (value, updated) = if self
+            .flags
+            .contains(mongoc_find_and_modify_flags_t::MONGOC_FIND_AND_MODIFY_REMOVE)
+        {
+            let mut options: FindOneAndDeleteOptions =
+                mongodb::bson::from_document(self.extra.clone())?;
+            options.sort = self.sort.clone();
+            options.projection = self.fields.clone();
+            options.max_time = max_time.or(options.max_time);
+
+            let value = match session {
+                Some(s) => collection.find_one_and_delete_with_session(filter, options, s)?,
+                None => collection.find_one_and_delete(filter, options)?,
+            };
+            (value, false)
+        } else {
+            let update = match self.update {
+                Some(ref u) => Update::parse(u)?,
+                None => anyhow::bail!("findAndModify requires either an update or remove"),
+            };
+
+            match update {
+                Update::Replacement(replacement) => {
+                    let mut options: FindOneAndReplaceOptions =
+                        mongodb::bson::from_document(self.extra.clone())?;
+                    options.sort = self.sort.clone();
+                    options.projection = self.fields.clone();
+                    options.max_time = max_time.or(options.max_time);
+                    options.upsert = Some(upsert);
+                    options.return_document = Some(return_document);
+                    options.bypass_document_validation = self.bypass_document_validation;
+
+                    let value = match session {
+                        Some(s) => collection.find_one_and_replace_with_session(
+                            filter,
+                            replacement,
+                            options,
+                            s,
+                        )?,
+                        None => collection.find_one_and_replace(filter, replacement, options)?,
+                    };
+                    (value, true)
+                }
+                Update::Modifications(modifications) => {
+                    let mut options: FindOneAndUpdateOptions =
+                        mongodb::bson::from_document(self.extra.clone())?;
+                    options.sort = self.sort.clone();
+                    options.projection = self.fields.clone();
+                    options.max_time = max_time.or(options.max_time);
+                    options.upsert = Some(upsert);
+                    options.return_document = Some(return_document);
+                    options.bypass_document_validation = self.bypass_document_validation;
+
+                    let value = match session {
+                        Some(s) => collection.find_one_and_update_with_session(
+                            filter,
+                            modifications,
+                            options,
+                            s,
+                        )?,
+                        None => collection.find_one_and_update(filter, modifications, options)?,
+                    };
+                    (value, true)
+                }
+            }
+        };
+
+        // The driver's helpers only return the document, so lastErrorObject is reconstructed
+        // from whether there was one. They don't say whether an upsert happened, so "upserted"
+        // is never reported.
+        let mut last_error_object = RawDocumentBuf::new();
+        last_error_object.append("n", value.is_some() as i32);
+        if updated {
+            last_error_object.append("updatedExisting", value.is_some());
+        }
+
+        let mut reply = RawDocumentBuf::new();
+        reply.append("lastErrorObject", last_error_object);
+        match value {
+            Some(v) => reply.append("value", v),
+            None => reply.append("value", RawBson::Null),
+        }
+        reply.append("ok", 1.0);
+        Ok(reply)
+    }
+
+    /// maxTimeMS can be set either directly or through the extra options, where the driver's
+    /// options would otherwise not recognize it.
+    fn max_time_ms(&self) -> anyhow::Result<Option<Duration>> {
+        if let Some(ms) = self.max_time_ms {
+            return Ok(Some(Duration::from_millis(ms.into())));
+        }
+
+        match self.extra.get("maxTimeMS") {
+            Some(Bson::Int32(ms)) if *ms >= 0 => Ok(Some(Duration::from_millis(*ms as u64))),
+            Some(Bson::Int64(ms)) if *ms >= 0 => Ok(Some(Duration::from_millis(*ms as u64))),
+            Some(other) => anyhow::bail!("invalid maxTimeMS: {}", other),
+            None => Ok(None),
+        }
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_find_and_modify_opts_new() -> *mut mongoc_find_and_modify_opts_t {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_find_and_modify_opts_t::default()))
+    })
 }
 
 #[no_mangle]
@@ -20,7 +208,10 @@ pub unsafe extern "C" fn mongoc_find_and_modify_opts_set_bypass_document_validat
     opts: *mut mongoc_find_and_modify_opts_t,
     bypass: bool,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).bypass_document_validation = Some(bypass);
+        true
+    })
 }
 
 #[no_mangle]
@@ -28,7 +219,20 @@ pub unsafe extern "C" fn mongoc_find_and_modify_opts_set_fields(
     opts: *mut mongoc_find_and_modify_opts_t,
     fields: *const bson_t<'static>,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        if fields.is_null() {
+            (*opts).fields = None;
+            return true;
+        }
+
+        match (*fields).to_document() {
+            Ok(f) => {
+                (*opts).fields = Some(f);
+                true
+            }
+            Err(_) => false,
+        }
+    })
 }
 
 #[no_mangle]
@@ -36,7 +240,10 @@ pub unsafe extern "C" fn mongoc_find_and_modify_opts_set_flags(
     opts: *mut mongoc_find_and_modify_opts_t,
     flags: mongoc_find_and_modify_flags_t,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).flags = flags;
+        true
+    })
 }
 
 #[no_mangle]
@@ -44,7 +251,20 @@ pub unsafe extern "C" fn mongoc_find_and_modify_opts_set_sort(
     opts: *mut mongoc_find_and_modify_opts_t,
     sort: *const bson_t<'static>,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        if sort.is_null() {
+            (*opts).sort = None;
+            return true;
+        }
+
+        match (*sort).to_document() {
+            Ok(s) => {
+                (*opts).sort = Some(s);
+                true
+            }
+            Err(_) => false,
+        }
+    })
 }
 
 #[no_mangle]
@@ -52,7 +272,25 @@ pub unsafe extern "C" fn mongoc_find_and_modify_opts_set_update(
     opts: *mut mongoc_find_and_modify_opts_t,
     update: *const bson_t<'static>,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).update = if update.is_null() {
+            None
+        } else {
+            Some((*update).to_raw_document_buf())
+        };
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_find_and_modify_opts_set_max_time_ms(
+    opts: *mut mongoc_find_and_modify_opts_t,
+    max_time_ms: u32,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).max_time_ms = Some(max_time_ms);
+        true
+    })
 }
 
 #[no_mangle]
@@ -60,12 +298,22 @@ pub unsafe extern "C" fn mongoc_find_and_modify_opts_append(
     opts: *mut mongoc_find_and_modify_opts_t,
     extra: *const bson_t<'static>,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || match (*extra).to_document() {
+        Ok(e) => {
+            (*opts).extra.extend(e);
+            true
+        }
+        Err(_) => false,
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_find_and_modify_opts_destroy(
     opts: *mut mongoc_find_and_modify_opts_t,
 ) {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        if !opts.is_null() {
+            drop(Box::from_raw(opts))
+        }
+    })
 }
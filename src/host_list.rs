@@ -0,0 +1,78 @@
+use std::os::raw::{c_char, c_void};
+
+pub const BSON_HOST_NAME_MAX: usize = 255;
+
+const AF_UNSPEC: i32 = 0;
+const AF_UNIX: i32 = 1;
+#[cfg(target_os = "windows")]
+const AF_INET6: i32 = 23;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const AF_INET6: i32 = 30;
+#[cfg(target_os = "freebsd")]
+const AF_INET6: i32 = 28;
+#[cfg(not(any(
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+const AF_INET6: i32 = 10;
+
+/// A server address, with the same layout as libmongoc's so C code can walk the list directly.
+/// Used for URI seed lists, APM events and server descriptions.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct mongoc_host_list_t {
+    pub next: *mut mongoc_host_list_t,
+    pub host: [c_char; BSON_HOST_NAME_MAX + 1],
+    pub host_and_port: [c_char; BSON_HOST_NAME_MAX + 7],
+    pub port: u16,
+    pub family: i32,
+    pub padding: [*mut c_void; 4],
+}
+
+impl mongoc_host_list_t {
+    /// Creates an unlinked entry for `host`. As in libmongoc, a host ending in ".sock" is the
+    /// path of a Unix domain socket, and a host containing ':' is an IPv6 address.
+    pub(crate) fn new(host: &str, port: u16) -> Self {
+        let (host_and_port, family) = if host.ends_with(".sock") {
+            (host.to_string(), AF_UNIX)
+        } else if host.contains(':') {
+            (format!("[{}]:{}", host, port), AF_INET6)
+        } else {
+            (format!("{}:{}", host, port), AF_UNSPEC)
+        };
+
+        let mut entry = Self {
+            next: std::ptr::null_mut(),
+            host: [0; BSON_HOST_NAME_MAX + 1],
+            host_and_port: [0; BSON_HOST_NAME_MAX + 7],
+            port,
+            family,
+            padding: [std::ptr::null_mut(); 4],
+        };
+        copy_truncated(&mut entry.host, host);
+        copy_truncated(&mut entry.host_and_port, &host_and_port);
+        entry
+    }
+
+    /// Builds a linked list from `hosts`. The entries point into the returned Vec's buffer, so
+    /// the Vec mustn't be modified afterwards, and the first entry is the head of the list.
+    pub(crate) fn list(hosts: impl IntoIterator<Item = Self>) -> Vec<Self> {
+        let mut list: Vec<Self> = hosts.into_iter().collect();
+        let head = list.as_mut_ptr();
+        for i in 1..list.len() {
+            list[i - 1].next = unsafe { head.add(i) };
+        }
+        list
+    }
+}
+
+/// Copies `s` into a C string buffer, truncating it to fit along with its NUL terminator.
+fn copy_truncated(buf: &mut [c_char], s: &str) {
+    let len = s.len().min(buf.len() - 1);
+    for (dst, src) in buf.iter_mut().zip(&s.as_bytes()[..len]) {
+        *dst = *src as c_char;
+    }
+    buf[len] = 0;
+}
@@ -0,0 +1,286 @@
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_void},
+};
+
+use mongodb::bson::{doc, Bson, Document, RawDocument};
+
+use crate::{bson::bson_t, error::catch_panic};
+
+/// An index to create with mongoc_collection_create_indexes_with_opts: its key pattern and the
+/// options of the index specification, such as "name" or "unique".
+pub struct mongoc_index_model_t {
+    keys: Document,
+    opts: Document,
+}
+
+impl mongoc_index_model_t {
+    /// The index specification sent to the createIndexes command. Like libmongoc, an index
+    /// without an explicit name is named after its keys.
+    pub(crate) fn to_index_spec(&self) -> anyhow::Result<Document> {
+        let mut spec = doc! { "key": self.keys.clone() };
+        if !self.opts.contains_key("name") {
+            spec.insert("name", keys_to_index_string(&self.keys)?);
+        }
+        spec.extend(self.opts.clone());
+        Ok(spec)
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct mongoc_index_opt_geo_t {
+    pub twod_sphere_version: u8,
+    pub twod_bits_precision: u8,
+    pub twod_location_min: f64,
+    pub twod_location_max: f64,
+    pub haystack_bucket_size: f64,
+    pub padding: [*mut u8; 32],
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct mongoc_index_opt_storage_t {
+    pub r#type: i32,
+}
+
+pub const MONGOC_INDEX_STORAGE_OPT_MMAPV1: i32 = 0;
+pub const MONGOC_INDEX_STORAGE_OPT_WIREDTIGER: i32 = 1;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct mongoc_index_opt_wt_t {
+    pub base: mongoc_index_opt_storage_t,
+    pub config_str: *const c_char,
+    pub padding: [*mut c_void; 8],
+}
+
+/// The legacy index options taken by mongoc_collection_create_index_with_opts, with the same
+/// layout as libmongoc's so C code can fill it in directly.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub struct mongoc_index_opt_t {
+    pub is_initialized: bool,
+    pub background: bool,
+    pub unique: bool,
+    pub name: *const c_char,
+    pub drop_dups: bool,
+    pub sparse: bool,
+    pub expire_after_seconds: i32,
+    pub v: i32,
+    pub weights: *const bson_t<'static>,
+    pub default_language: *const c_char,
+    pub language_override: *const c_char,
+    pub geo_options: *mut mongoc_index_opt_geo_t,
+    pub storage_options: *mut mongoc_index_opt_storage_t,
+    pub partial_filter_expression: *const bson_t<'static>,
+    pub collation: *const bson_t<'static>,
+    pub padding: [*mut c_void; 4],
+}
+
+impl Default for mongoc_index_opt_t {
+    fn default() -> Self {
+        Self {
+            is_initialized: true,
+            background: false,
+            unique: false,
+            name: std::ptr::null(),
+            drop_dups: false,
+            sparse: false,
+            expire_after_seconds: -1,
+            v: -1,
+            weights: std::ptr::null(),
+            default_language: std::ptr::null(),
+            language_override: std::ptr::null(),
+            geo_options: std::ptr::null_mut(),
+            storage_options: std::ptr::null_mut(),
+            partial_filter_expression: std::ptr::null(),
+            collation: std::ptr::null(),
+            padding: [std::ptr::null_mut(); 4],
+        }
+    }
+}
+
+impl Default for mongoc_index_opt_geo_t {
+    fn default() -> Self {
+        Self {
+            twod_sphere_version: 0,
+            twod_bits_precision: 26,
+            twod_location_min: -90.0,
+            twod_location_max: 90.0,
+            haystack_bucket_size: 0.0,
+            padding: [std::ptr::null_mut(); 32],
+        }
+    }
+}
+
+impl Default for mongoc_index_opt_wt_t {
+    fn default() -> Self {
+        Self {
+            base: mongoc_index_opt_storage_t {
+                r#type: MONGOC_INDEX_STORAGE_OPT_WIREDTIGER,
+            },
+            config_str: std::ptr::null(),
+            padding: [std::ptr::null_mut(); 8],
+        }
+    }
+}
+
+impl mongoc_index_opt_t {
+    /// The index specification these options describe for an index on `keys`. Only options that
+    /// differ from their defaults are included, as libmongoc does.
+    pub(crate) unsafe fn to_index_spec(&self, keys: &RawDocument) -> anyhow::Result<Document> {
+        let cstr = |s: *const c_char| CStr::from_ptr(s).to_string_lossy().into_owned();
+
+        let keys: Document = keys.try_into()?;
+        let name = if self.name.is_null() {
+            keys_to_index_string(&keys)?
+        } else {
+            cstr(self.name)
+        };
+        let mut spec = doc! { "key": keys, "name": name };
+
+        if self.background {
+            spec.insert("background", true);
+        }
+        if self.unique {
+            spec.insert("unique", true);
+        }
+        if self.drop_dups {
+            spec.insert("dropDups", true);
+        }
+        if self.sparse {
+            spec.insert("sparse", true);
+        }
+        if self.expire_after_seconds != -1 {
+            spec.insert("expireAfterSeconds", self.expire_after_seconds);
+        }
+        if self.v != -1 {
+            spec.insert("v", self.v);
+        }
+        if !self.weights.is_null() {
+            spec.insert("weights", (*self.weights).to_document()?);
+        }
+        if !self.default_language.is_null() {
+            spec.insert("default_language", cstr(self.default_language));
+        }
+        if !self.language_override.is_null() {
+            spec.insert("language_override", cstr(self.language_override));
+        }
+        if !self.partial_filter_expression.is_null() {
+            spec.insert(
+                "partialFilterExpression",
+                (*self.partial_filter_expression).to_document()?,
+            );
+        }
+        if !self.collation.is_null() {
+            spec.insert("collation", (*self.collation).to_document()?);
+        }
+
+        if let Some(geo) = self.geo_options.as_ref() {
+            let default = mongoc_index_opt_geo_t::default();
+            if geo.twod_sphere_version != default.twod_sphere_version {
+                spec.insert("2dsphereIndexVersion", geo.twod_sphere_version as i32);
+            }
+            if geo.twod_bits_precision != default.twod_bits_precision {
+                spec.insert("bits", geo.twod_bits_precision as i32);
+            }
+            if geo.twod_location_min != default.twod_location_min {
+                spec.insert("min", geo.twod_location_min);
+            }
+            if geo.twod_location_max != default.twod_location_max {
+                spec.insert("max", geo.twod_location_max);
+            }
+            if geo.haystack_bucket_size != default.haystack_bucket_size {
+                spec.insert("bucketSize", geo.haystack_bucket_size);
+            }
+        }
+
+        if let Some(storage) = self.storage_options.as_ref() {
+            if storage.r#type == MONGOC_INDEX_STORAGE_OPT_WIREDTIGER {
+                let wt = &*(self.storage_options as *const mongoc_index_opt_wt_t);
+                if !wt.config_str.is_null() {
+                    spec.insert(
+                        "storageEngine",
+                        doc! { "wiredTiger": { "configString": cstr(wt.config_str) } },
+                    );
+                }
+            }
+        }
+
+        Ok(spec)
+    }
+}
+
+/// Generates the default name of an index from its keys, e.g. `{a: 1, b: -1}` becomes `a_1_b_-1`.
+pub(crate) fn keys_to_index_string(keys: &Document) -> anyhow::Result<String> {
+    let parts = keys
+        .iter()
+        .map(|(key, value)| match value {
+            Bson::String(s) => Ok(format!("{}_{}", key, s)),
+            Bson::Int32(i) => Ok(format!("{}_{}", key, i)),
+            Bson::Int64(i) => Ok(format!("{}_{}", key, i)),
+            Bson::Double(d) => Ok(format!("{}_{}", key, *d as i64)),
+            other => anyhow::bail!(
+                "Invalid index key \"{}\" of type {:?}",
+                key,
+                other.element_type()
+            ),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(parts.join("_"))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_index_model_new(
+    keys: *const bson_t<'static>,
+    opts: *const bson_t<'static>,
+) -> *mut mongoc_index_model_t {
+    catch_panic(std::ptr::null_mut(), || {
+        let result: anyhow::Result<_> = (|| {
+            let opts = match opts.as_ref() {
+                Some(opts) => opts.to_document()?,
+                None => Document::new(),
+            };
+            Ok(mongoc_index_model_t {
+                keys: (*keys).to_document()?,
+                opts,
+            })
+        })();
+
+        match result {
+            Ok(model) => Box::into_raw(Box::new(model)),
+            Err(_) => std::ptr::null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_index_model_destroy(model: *mut mongoc_index_model_t) {
+    catch_panic(std::ptr::null_mut(), || {
+        if !model.is_null() {
+            drop(Box::from_raw(model))
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_index_opt_init(opt: *mut mongoc_index_opt_t) {
+    catch_panic(std::ptr::null_mut(), || {
+        opt.write(mongoc_index_opt_t::default())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_index_opt_geo_init(opt: *mut mongoc_index_opt_geo_t) {
+    catch_panic(std::ptr::null_mut(), || {
+        opt.write(mongoc_index_opt_geo_t::default())
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_index_opt_wt_init(opt: *mut mongoc_index_opt_wt_t) {
+    catch_panic(std::ptr::null_mut(), || {
+        opt.write(mongoc_index_opt_wt_t::default())
+    })
+}
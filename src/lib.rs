@@ -10,14 +10,19 @@ mod cursor;
 mod database;
 pub mod error;
 pub mod find_and_modify;
+pub mod host_list;
+pub mod index;
 pub mod read_concern;
 pub mod read_pref;
 pub mod session;
+pub mod srv;
 pub mod uri;
 pub mod write_concern;
 
 use std::os::raw::c_char;
 
+use bitflags::bitflags;
+
 #[no_mangle]
 pub extern "C" fn mongoc_init() {}
 
@@ -34,16 +39,54 @@ pub extern "C" fn mongoc_handshake_data_append(
 #[no_mangle]
 pub extern "C" fn mongoc_cleanup() {}
 
-#[repr(C)]
-pub enum mongoc_query_flags_t {
-    MONGOC_QUERY_NONE = 0,
-    MONGOC_QUERY_TAILABLE_CURSOR = 1 << 1,
-    MONGOC_QUERY_SECONDARY_OK = 1 << 2,
-    MONGOC_QUERY_OPLOG_REPLAY = 1 << 3,
-    MONGOC_QUERY_NO_CURSOR_TIMEOUT = 1 << 4,
-    MONGOC_QUERY_AWAIT_DATA = 1 << 5,
-    MONGOC_QUERY_EXHAUST = 1 << 6,
-    MONGOC_QUERY_PARTIAL = 1 << 7,
+bitflags! {
+    #[allow(non_camel_case_types)]
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct mongoc_query_flags_t: u32 {
+        const MONGOC_QUERY_NONE = 0;
+        const MONGOC_QUERY_TAILABLE_CURSOR = 1 << 1;
+        const MONGOC_QUERY_SECONDARY_OK = 1 << 2;
+        const MONGOC_QUERY_OPLOG_REPLAY = 1 << 3;
+        const MONGOC_QUERY_NO_CURSOR_TIMEOUT = 1 << 4;
+        const MONGOC_QUERY_AWAIT_DATA = 1 << 5;
+        const MONGOC_QUERY_EXHAUST = 1 << 6;
+        const MONGOC_QUERY_PARTIAL = 1 << 7;
+    }
+}
+
+bitflags! {
+    #[allow(non_camel_case_types)]
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct mongoc_insert_flags_t: u32 {
+        const MONGOC_INSERT_NONE = 0;
+        const MONGOC_INSERT_CONTINUE_ON_ERROR = 1 << 0;
+        const MONGOC_INSERT_NO_VALIDATE = 1 << 31;
+    }
+}
+
+bitflags! {
+    #[allow(non_camel_case_types)]
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct mongoc_update_flags_t: u32 {
+        const MONGOC_UPDATE_NONE = 0;
+        const MONGOC_UPDATE_UPSERT = 1 << 0;
+        const MONGOC_UPDATE_MULTI_UPDATE = 1 << 1;
+        const MONGOC_UPDATE_NO_VALIDATE = 1 << 31;
+    }
+}
+
+bitflags! {
+    #[allow(non_camel_case_types)]
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct mongoc_remove_flags_t: u32 {
+        const MONGOC_REMOVE_NONE = 0;
+        const MONGOC_REMOVE_SINGLE_REMOVE = 1 << 0;
+    }
 }
 
+pub const MONGOC_ERROR_API_VERSION_LEGACY: i32 = 1;
 pub const MONGOC_ERROR_API_VERSION_2: i32 = 2;
@@ -1,64 +1,88 @@
 use std::{
-    ffi::CStr,
-    ops::{Deref, DerefMut},
+    ffi::{CStr, CString},
     os::raw::c_char,
 };
 
-use mongodb::options::{ReadConcern, ReadConcernLevel};
+use mongodb::{
+    bson::RawDocumentBuf,
+    options::{ReadConcern, ReadConcernLevel},
+};
+
+use crate::{bson::bson_t, error::catch_panic};
 
+#[derive(Clone, Default)]
 pub struct mongoc_read_concern_t {
     rust_read_concern: Option<ReadConcern>,
+    /// The level as a C string, kept for mongoc_read_concern_get_level.
+    level: Option<CString>,
 }
 
-impl From<ReadConcern> for mongoc_read_concern_t {
-    fn from(rc: ReadConcern) -> Self {
-        Self {
-            rust_read_concern: rc.into(),
-        }
+impl mongoc_read_concern_t {
+    /// The read concern to configure the driver with, or None to use the server's default.
+    pub(crate) fn rust_read_concern(&self) -> Option<&ReadConcern> {
+        self.rust_read_concern.as_ref()
+    }
+
+    fn set_level(&mut self, level: Option<ReadConcernLevel>) {
+        self.level = level
+            .as_ref()
+            .and_then(|level| CString::new(level_str(level)).ok());
+        self.rust_read_concern = level.map(ReadConcern::from);
     }
 }
 
-// impl Deref for mongoc_read_concern_t {
-//     type Target = ReadConcern;
+/// The level as it's spelled in a readConcern document.
+pub(crate) fn level_str(level: &ReadConcernLevel) -> &str {
+    match level {
+        ReadConcernLevel::Local => "local",
+        ReadConcernLevel::Majority => "majority",
+        ReadConcernLevel::Linearizable => "linearizable",
+        ReadConcernLevel::Available => "available",
+        ReadConcernLevel::Snapshot => "snapshot",
+        ReadConcernLevel::Custom(level) => level,
+        _ => unreachable!(),
+    }
+}
 
-//     fn deref(&self) -> &Self::Target {
-//         &self.rust_read_concern
-//     }
-// }
+impl From<ReadConcern> for mongoc_read_concern_t {
+    fn from(rc: ReadConcern) -> Self {
+        Some(rc).into()
+    }
+}
 
-// impl DerefMut for mongoc_read_concern_t {
-//     fn deref_mut(&mut self) -> &mut Self::Target {
-//         &mut self.rust_read_concern
-//     }
-// }
+impl From<Option<ReadConcern>> for mongoc_read_concern_t {
+    fn from(rc: Option<ReadConcern>) -> Self {
+        let mut read_concern = Self::default();
+        read_concern.set_level(rc.map(|rc| rc.level));
+        read_concern
+    }
+}
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_concern_new() -> *mut mongoc_read_concern_t {
-    Box::into_raw(Box::new(mongoc_read_concern_t {
-        rust_read_concern: None,
-    }))
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_read_concern_t::default()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_concern_copy(
+    rc: *const mongoc_read_concern_t,
+) -> *mut mongoc_read_concern_t {
+    catch_panic(std::ptr::null_mut(), || match rc.as_ref() {
+        Some(rc) => Box::into_raw(Box::new(rc.clone())),
+        None => std::ptr::null_mut(),
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_concern_get_level(
     rc: *const mongoc_read_concern_t,
 ) -> *const c_char {
-    let level_bytes = match (*rc).rust_read_concern {
-        Some(ref rc) => Some(match rc.level {
-            ReadConcernLevel::Local => "local\0".as_bytes(),
-            ReadConcernLevel::Available => "available\0".as_bytes(),
-            ReadConcernLevel::Linearizable => "linearizeable\0".as_bytes(),
-            ReadConcernLevel::Majority => "majority\0".as_bytes(),
-            ReadConcernLevel::Snapshot => "snapshot\0".as_bytes(),
-            ref c => panic!("cant return custom rc level {:?}", c),
-        }),
-        None => None,
-    };
-
-    match level_bytes {
-        Some(b) => CStr::from_bytes_with_nul(b).unwrap().as_ptr() as *const c_char,
+    catch_panic(std::ptr::null_mut(), || match (*rc).level {
+        Some(ref level) => level.as_ptr(),
         None => std::ptr::null(),
-    }
+    })
 }
 
 #[no_mangle]
@@ -66,21 +90,57 @@ pub unsafe extern "C" fn mongoc_read_concern_set_level(
     rc: *mut mongoc_read_concern_t,
     level: *const c_char,
 ) -> bool {
-    let level_str = CStr::from_ptr(level).to_string_lossy();
-    let level = match level_str.as_ref() {
-        "available" => ReadConcernLevel::Available,
-        "local" => ReadConcernLevel::Local,
-        "linearizeable" => ReadConcernLevel::Linearizable,
-        "majority" => ReadConcernLevel::Majority,
-        "snapshot" => ReadConcernLevel::Snapshot,
-        other => ReadConcernLevel::Custom(other.to_string()),
-    };
-
-    (*rc).rust_read_concern = Some(level.into());
-    true
+    catch_panic(std::ptr::null_mut(), || {
+        if level.is_null() {
+            (*rc).set_level(None);
+            return true;
+        }
+
+        let level_str = CStr::from_ptr(level).to_string_lossy();
+        let level = match level_str.as_ref() {
+            "available" => ReadConcernLevel::Available,
+            "local" => ReadConcernLevel::Local,
+            "linearizable" => ReadConcernLevel::Linearizable,
+            "majority" => ReadConcernLevel::Majority,
+            "snapshot" => ReadConcernLevel::Snapshot,
+            // Levels the driver doesn't know about are passed through to the server as is.
+            other => ReadConcernLevel::Custom(other.to_string()),
+        };
+
+        (*rc).set_level(Some(level));
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_concern_is_default(rc: *const mongoc_read_concern_t) -> bool {
+    catch_panic(std::ptr::null_mut(), || match rc.as_ref() {
+        Some(rc) => rc.rust_read_concern.is_none(),
+        None => true,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_concern_append(
+    rc: *mut mongoc_read_concern_t,
+    command: *mut bson_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        // Like libmongoc, a default read concern appends nothing.
+        if let Some(ref level) = (*rc).rust_read_concern {
+            let mut read_concern = RawDocumentBuf::new();
+            read_concern.append("level", level_str(&level.level));
+            (*command).doc.to_mut().append("readConcern", read_concern);
+        }
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_concern_destroy(rc: *mut mongoc_read_concern_t) {
-    drop(Box::from_raw(rc))
+    catch_panic(std::ptr::null_mut(), || {
+        if !rc.is_null() {
+            drop(Box::from_raw(rc))
+        }
+    })
 }
@@ -1,31 +1,57 @@
-use std::{ops::Deref, time::Duration};
+use std::time::Duration;
 
-use mongodb::options::ReadPreference;
+use mongodb::{
+    bson::{RawBsonRef, RawDocument, RawDocumentBuf},
+    options::{
+        HedgedReadOptions, ReadPreference, ReadPreferenceOptions, SelectionCriteria, TagSet,
+    },
+};
 
-use crate::bson::bson_t;
+use crate::{
+    bson::bson_t,
+    error::{catch_panic, FailureValue},
+};
 
 pub const MONGOC_SMALLEST_MAX_STALENESS_SECONDS: i64 = 90;
 pub const MONGOC_NO_MAX_STALENESS: i64 = -1;
 
 pub struct mongoc_read_prefs_t {
-    rust_read_pref: ReadPreference,
+    mode: mongoc_read_mode_t,
+    options: ReadPreferenceOptions,
+    /// The tag sets as a BSON array, kept in sync with `options` for mongoc_read_prefs_get_tags.
+    tags: bson_t<'static>,
+    /// The hedge options as BSON, kept in sync with `options` for mongoc_read_prefs_get_hedge.
+    hedge: bson_t<'static>,
 }
 
-impl Deref for mongoc_read_prefs_t {
-    type Target = ReadPreference;
-
-    fn deref(&self) -> &Self::Target {
-        &self.rust_read_pref
+impl Clone for mongoc_read_prefs_t {
+    fn clone(&self) -> Self {
+        Self::with_options(self.mode, self.options.clone())
     }
 }
 
 impl From<ReadPreference> for mongoc_read_prefs_t {
     fn from(rp: ReadPreference) -> Self {
-        Self { rust_read_pref: rp }
+        match rp {
+            ReadPreference::Primary => Self::new(mongoc_read_mode_t::MONGOC_READ_PRIMARY),
+            ReadPreference::PrimaryPreferred { options } => {
+                Self::with_options(mongoc_read_mode_t::MONGOC_READ_PRIMARY_PREFERRED, options)
+            }
+            ReadPreference::Secondary { options } => {
+                Self::with_options(mongoc_read_mode_t::MONGOC_READ_SECONDARY, options)
+            }
+            ReadPreference::SecondaryPreferred { options } => {
+                Self::with_options(mongoc_read_mode_t::MONGOC_READ_SECONDARY_PREFERRED, options)
+            }
+            ReadPreference::Nearest { options } => {
+                Self::with_options(mongoc_read_mode_t::MONGOC_READ_NEAREST, options)
+            }
+        }
     }
 }
 
 #[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum mongoc_read_mode_t {
     MONGOC_READ_PRIMARY = (1 << 0),
     MONGOC_READ_SECONDARY = (1 << 1),
@@ -34,10 +60,161 @@ pub enum mongoc_read_mode_t {
     MONGOC_READ_NEAREST = (1 << 3) | (1 << 1),
 }
 
+impl FailureValue for mongoc_read_mode_t {
+    fn failure() -> Self {
+        mongoc_read_mode_t::MONGOC_READ_PRIMARY
+    }
+}
+
 impl mongoc_read_prefs_t {
-    fn new() -> Self {
-        mongoc_read_prefs_t {
-            rust_read_pref: ReadPreference::Primary,
+    fn new(mode: mongoc_read_mode_t) -> Self {
+        Self::with_options(mode, Default::default())
+    }
+
+    fn with_options(mode: mongoc_read_mode_t, options: ReadPreferenceOptions) -> Self {
+        let mut rp = mongoc_read_prefs_t {
+            mode,
+            options,
+            tags: RawDocumentBuf::new().into(),
+            hedge: RawDocumentBuf::new().into(),
+        };
+        rp.update_bson();
+        rp
+    }
+
+    /// Regenerates the BSON returned by the tags and hedge getters after `options` changed.
+    fn update_bson(&mut self) {
+        let mut tags = RawDocumentBuf::new();
+        for (i, tag_set) in self.options.tag_sets.iter().flatten().enumerate() {
+            // Tag sets are hash maps in the driver, so sort them to keep the output stable.
+            let mut tag_set: Vec<_> = tag_set.iter().collect();
+            tag_set.sort();
+
+            let mut doc = RawDocumentBuf::new();
+            for (key, value) in tag_set {
+                doc.append(key, value.as_str());
+            }
+            tags.append(i.to_string(), doc);
+        }
+        self.tags = tags.into();
+
+        let mut hedge = RawDocumentBuf::new();
+        if let Some(ref options) = self.options.hedge {
+            hedge.append("enabled", options.enabled);
+        }
+        self.hedge = hedge.into();
+    }
+
+    /// Checks the read preference the way libmongoc does before using it: primary reads can't
+    /// have tags, a max staleness or hedging, and a max staleness must be at least
+    /// MONGOC_SMALLEST_MAX_STALENESS_SECONDS.
+    fn is_valid(&self) -> bool {
+        if self.mode == mongoc_read_mode_t::MONGOC_READ_PRIMARY
+            && (self.options.tag_sets.is_some()
+                || self.options.max_staleness.is_some()
+                || self.options.hedge.is_some())
+        {
+            return false;
+        }
+
+        match self.options.max_staleness {
+            Some(max_staleness) => {
+                max_staleness.as_secs() >= MONGOC_SMALLEST_MAX_STALENESS_SECONDS as u64
+            }
+            None => true,
+        }
+    }
+
+    pub(crate) fn read_preference(&self) -> ReadPreference {
+        let options = self.options.clone();
+        match self.mode {
+            mongoc_read_mode_t::MONGOC_READ_PRIMARY => ReadPreference::Primary,
+            mongoc_read_mode_t::MONGOC_READ_PRIMARY_PREFERRED => {
+                ReadPreference::PrimaryPreferred { options }
+            }
+            mongoc_read_mode_t::MONGOC_READ_SECONDARY => ReadPreference::Secondary { options },
+            mongoc_read_mode_t::MONGOC_READ_SECONDARY_PREFERRED => {
+                ReadPreference::SecondaryPreferred { options }
+            }
+            mongoc_read_mode_t::MONGOC_READ_NEAREST => ReadPreference::Nearest { options },
+        }
+    }
+
+    pub(crate) fn selection_criteria(&self) -> SelectionCriteria {
+        SelectionCriteria::ReadPreference(self.read_preference())
+    }
+}
+
+/// Resolves the read preference of a read operation the way libmongoc does: an explicit
+/// `read_prefs` argument takes precedence over a "readPreference" in `opts`. None means the
+/// operation falls back to its default.
+pub(crate) unsafe fn resolve_selection_criteria(
+    read_prefs: *const mongoc_read_prefs_t,
+    opts: *const bson_t,
+) -> anyhow::Result<Option<SelectionCriteria>> {
+    if let Some(read_prefs) = read_prefs.as_ref() {
+        if !read_prefs.is_valid() {
+            anyhow::bail!("Invalid mongoc_read_prefs_t");
+        }
+        return Ok(Some(read_prefs.selection_criteria()));
+    }
+
+    let value = match opts.as_ref() {
+        Some(opts) => opts.get("readPreference")?,
+        None => None,
+    };
+    match value {
+        Some(RawBsonRef::Document(doc)) => {
+            let read_pref: ReadPreference = mongodb::bson::from_slice(doc.as_bytes())?;
+            Ok(Some(SelectionCriteria::ReadPreference(read_pref)))
+        }
+        Some(other) => anyhow::bail!(
+            "Invalid \"readPreference\" in opts, expected a document but got {:?}",
+            other.element_type()
+        ),
+        None => Ok(None),
+    }
+}
+
+/// Parses a BSON array of tag documents, such as `[{dc: "ny", rack: "1"}, {}]`, into tag sets.
+fn parse_tag_sets(tags: &RawDocument) -> anyhow::Result<Vec<TagSet>> {
+    tags.into_iter()
+        .map(|kvp| match kvp?.1 {
+            RawBsonRef::Document(doc) => parse_tag_set(doc),
+            other => anyhow::bail!(
+                "Invalid read preference tag set, expected a document but got {:?}",
+                other.element_type()
+            ),
+        })
+        .collect()
+}
+
+fn parse_tag_set(tag: &RawDocument) -> anyhow::Result<TagSet> {
+    tag.into_iter()
+        .map(|kvp| match kvp? {
+            (key, RawBsonRef::String(value)) => Ok((key.to_string(), value.to_string())),
+            (key, other) => anyhow::bail!(
+                "Invalid read preference tag \"{}\", expected a string but got {:?}",
+                key,
+                other.element_type()
+            ),
+        })
+        .collect()
+}
+
+impl Default for mongoc_read_prefs_t {
+    fn default() -> Self {
+        Self::new(mongoc_read_mode_t::MONGOC_READ_PRIMARY)
+    }
+}
+
+impl From<Option<&SelectionCriteria>> for mongoc_read_prefs_t {
+    /// Read preferences for a driver handle's selection criteria. Predicates can't be expressed
+    /// as libmongoc read preferences, so they're reported as primary.
+    fn from(criteria: Option<&SelectionCriteria>) -> Self {
+        match criteria {
+            Some(SelectionCriteria::ReadPreference(rp)) => rp.clone().into(),
+            _ => Self::default(),
         }
     }
 }
@@ -46,64 +223,135 @@ impl mongoc_read_prefs_t {
 pub unsafe extern "C" fn mongoc_read_prefs_new(
     mode: mongoc_read_mode_t,
 ) -> *mut mongoc_read_prefs_t {
-    let rp = match mode {
-        mongoc_read_mode_t::MONGOC_READ_PRIMARY => ReadPreference::Primary,
-        mongoc_read_mode_t::MONGOC_READ_PRIMARY_PREFERRED => ReadPreference::PrimaryPreferred {
-            options: Default::default(),
-        },
-        mongoc_read_mode_t::MONGOC_READ_SECONDARY => ReadPreference::Secondary {
-            options: Default::default(),
-        },
-        mongoc_read_mode_t::MONGOC_READ_SECONDARY_PREFERRED => ReadPreference::SecondaryPreferred {
-            options: Default::default(),
-        },
-        mongoc_read_mode_t::MONGOC_READ_NEAREST => ReadPreference::Nearest {
-            options: Default::default(),
-        },
-    };
-    Box::into_raw(Box::new(rp.into()))
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_read_prefs_t::new(mode)))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_prefs_copy(
+    rp: *const mongoc_read_prefs_t,
+) -> *mut mongoc_read_prefs_t {
+    catch_panic(std::ptr::null_mut(), || match rp.as_ref() {
+        Some(rp) => Box::into_raw(Box::new(rp.clone())),
+        None => std::ptr::null_mut(),
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_prefs_get_mode(
     rp: *const mongoc_read_prefs_t,
 ) -> mongoc_read_mode_t {
-    match &(*rp).rust_read_pref {
-        ReadPreference::Primary => mongoc_read_mode_t::MONGOC_READ_PRIMARY,
-        ReadPreference::PrimaryPreferred { .. } => {
-            mongoc_read_mode_t::MONGOC_READ_PRIMARY_PREFERRED
-        }
-        ReadPreference::Secondary { .. } => mongoc_read_mode_t::MONGOC_READ_SECONDARY,
-        ReadPreference::SecondaryPreferred { .. } => {
-            mongoc_read_mode_t::MONGOC_READ_SECONDARY_PREFERRED
-        }
-        ReadPreference::Nearest { .. } => mongoc_read_mode_t::MONGOC_READ_NEAREST,
-    }
+    catch_panic(std::ptr::null_mut(), || (*rp).mode)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_prefs_set_mode(
+    rp: *mut mongoc_read_prefs_t,
+    mode: mongoc_read_mode_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*rp).mode = mode;
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_prefs_get_tags(
     rp: *const mongoc_read_prefs_t,
 ) -> *const bson_t<'static> {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || &(*rp).tags as *const _)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_prefs_set_tags(
-    rp: *const mongoc_read_prefs_t,
+    rp: *mut mongoc_read_prefs_t,
     tags: *const bson_t<'static>,
 ) -> bool {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || {
+        let tag_sets = match tags.as_ref() {
+            Some(tags) => match parse_tag_sets(tags) {
+                Ok(tag_sets) => tag_sets,
+                Err(_) => return false,
+            },
+            None => Vec::new(),
+        };
+
+        (*rp).options.tag_sets = if tag_sets.is_empty() {
+            None
+        } else {
+            Some(tag_sets)
+        };
+        (*rp).update_bson();
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_prefs_add_tag(
+    rp: *mut mongoc_read_prefs_t,
+    tag: *const bson_t<'static>,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        // A null tag adds the empty tag set, which matches any server.
+        let tag_set = match tag.as_ref() {
+            Some(tag) => match parse_tag_set(tag) {
+                Ok(tag_set) => tag_set,
+                Err(_) => return false,
+            },
+            None => TagSet::new(),
+        };
+
+        (*rp)
+            .options
+            .tag_sets
+            .get_or_insert_with(Vec::new)
+            .push(tag_set);
+        (*rp).update_bson();
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_prefs_get_hedge(
+    rp: *const mongoc_read_prefs_t,
+) -> *const bson_t<'static> {
+    catch_panic(std::ptr::null_mut(), || &(*rp).hedge as *const _)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_prefs_set_hedge(
+    rp: *mut mongoc_read_prefs_t,
+    hedge: *const bson_t<'static>,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        let hedge = match hedge.as_ref() {
+            Some(hedge) if !hedge.is_empty() => match hedge.get("enabled") {
+                Ok(Some(RawBsonRef::Boolean(enabled))) => {
+                    Some(HedgedReadOptions::with_enabled(enabled))
+                }
+                _ => return false,
+            },
+            _ => None,
+        };
+
+        (*rp).options.hedge = hedge;
+        (*rp).update_bson();
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_prefs_get_max_staleness_seconds(
     rp: *const mongoc_read_prefs_t,
 ) -> i64 {
-    (*rp)
-        .max_staleness()
-        .map(|d| d.as_secs() as i64)
-        .unwrap_or(MONGOC_NO_MAX_STALENESS)
+    catch_panic(std::ptr::null_mut(), || {
+        (*rp)
+            .options
+            .max_staleness
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(MONGOC_NO_MAX_STALENESS)
+    })
 }
 
 #[no_mangle]
@@ -111,19 +359,26 @@ pub unsafe extern "C" fn mongoc_read_prefs_set_max_staleness_seconds(
     rp: *mut mongoc_read_prefs_t,
     seconds: i64,
 ) -> bool {
-    let opts = match (*rp).rust_read_pref {
-        ReadPreference::Primary => return false,
-        ReadPreference::Secondary { ref mut options } => options,
-        ReadPreference::SecondaryPreferred { ref mut options } => options,
-        ReadPreference::PrimaryPreferred { ref mut options } => options,
-        ReadPreference::Nearest { ref mut options } => options,
-    };
+    catch_panic(std::ptr::null_mut(), || {
+        (*rp).options.max_staleness = match seconds {
+            MONGOC_NO_MAX_STALENESS => None,
+            s if s < 0 => return false,
+            s => Some(Duration::from_secs(s as u64)),
+        };
+        true
+    })
+}
 
-    opts.max_staleness = Duration::from_secs(seconds as u64).into();
-    true
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_read_prefs_is_valid(rp: *const mongoc_read_prefs_t) -> bool {
+    catch_panic(std::ptr::null_mut(), || (*rp).is_valid())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_read_prefs_destroy(rp: *mut mongoc_read_prefs_t) {
-    drop(Box::from_raw(rp))
+    catch_panic(std::ptr::null_mut(), || {
+        if !rp.is_null() {
+            drop(Box::from_raw(rp))
+        }
+    })
 }
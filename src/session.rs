@@ -1,10 +1,15 @@
 use std::{
+    collections::HashMap,
     ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex,
+    },
     time::Duration,
 };
 
 use mongodb::{
-    bson::{RawDocumentBuf, Timestamp},
+    bson::{RawBsonRef, RawDocumentBuf, Timestamp},
     options::{SessionOptions, TransactionOptions},
     sync::ClientSession,
     ClusterTime,
@@ -12,6 +17,7 @@ use mongodb::{
 
 use crate::{
     bson::{bson_error_t, bson_t},
+    error::{catch_panic, log_unsupported, set_error, FailureValue},
     read_concern::mongoc_read_concern_t,
     read_pref::mongoc_read_prefs_t,
     write_concern::mongoc_write_concern_t,
@@ -22,8 +28,21 @@ pub struct mongoc_client_session_t {
     rust_session: ClientSession,
     lsid: bson_t<'static>,
     cluster_time: bson_t<'static>,
+    error_api: i32,
+    /// The id passed in opts documents as "sessionId" to refer to this session.
+    client_session_id: i64,
+    /// Whether a transaction has been started and not yet committed or aborted. The driver
+    /// doesn't expose its transaction state, so it is tracked here.
+    pub(crate) in_transaction: bool,
 }
 
+static NEXT_CLIENT_SESSION_ID: AtomicI64 = AtomicI64::new(1);
+
+/// The live sessions by client session id, so operations can find the session named by the
+/// "sessionId" in their opts the way libmongoc does. Pointers are stored as addresses since raw
+/// pointers can't be shared between threads.
+static SESSIONS: Mutex<Option<HashMap<i64, usize>>> = Mutex::new(None);
+
 #[allow(non_camel_case_types)]
 #[repr(C)]
 pub enum mongoc_transaction_state_t {
@@ -34,13 +53,53 @@ pub enum mongoc_transaction_state_t {
     MONGOC_TRANSACTION_ABORTED = 0x04,
 }
 
-impl From<ClientSession> for mongoc_client_session_t {
-    fn from(s: ClientSession) -> Self {
+impl FailureValue for mongoc_transaction_state_t {
+    fn failure() -> Self {
+        mongoc_transaction_state_t::MONGOC_TRANSACTION_NONE
+    }
+}
+
+impl mongoc_client_session_t {
+    pub(crate) fn new(s: ClientSession, error_api: i32) -> Self {
         let id = RawDocumentBuf::from_document(s.id()).unwrap();
         Self {
             rust_session: s,
             lsid: id.into(),
             cluster_time: RawDocumentBuf::new().into(),
+            error_api,
+            client_session_id: NEXT_CLIENT_SESSION_ID.fetch_add(1, Ordering::SeqCst),
+            in_transaction: false,
+        }
+    }
+
+    /// Moves the session to the heap and registers it so it can be found by its session id.
+    pub(crate) fn into_raw(self) -> *mut Self {
+        let id = self.client_session_id;
+        let session = Box::into_raw(Box::new(self));
+        SESSIONS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(id, session as usize);
+        session
+    }
+
+    /// Finds the session referred to by the "sessionId" in `opts`, if there is one.
+    pub(crate) unsafe fn from_opts(opts: *const bson_t) -> anyhow::Result<*mut Self> {
+        if opts.is_null() {
+            return Ok(std::ptr::null_mut());
+        }
+
+        let id = match (*opts).get("sessionId")? {
+            Some(RawBsonRef::Int64(id)) => id,
+            Some(_) => anyhow::bail!("Invalid sessionId"),
+            None => return Ok(std::ptr::null_mut()),
+        };
+
+        let sessions = SESSIONS.lock().unwrap();
+        match sessions.as_ref().and_then(|s| s.get(&id)) {
+            Some(session) => Ok(*session as *mut Self),
+            None => anyhow::bail!("Invalid sessionId"),
         }
     }
 }
@@ -63,16 +122,22 @@ impl DerefMut for mongoc_client_session_t {
 pub unsafe extern "C" fn mongoc_client_session_get_lsid(
     session: *const mongoc_client_session_t,
 ) -> *const bson_t<'static> {
-    &(*session).lsid
+    catch_panic(std::ptr::null_mut(), || &(*session).lsid as *const _)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_append(
-    _session: *const mongoc_client_session_t,
-    _opts: *mut bson_t,
-    _error: *mut bson_error_t,
+    session: *const mongoc_client_session_t,
+    opts: *mut bson_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    todo!("lookup session by id")
+    catch_panic(error, || {
+        (*opts)
+            .doc
+            .to_mut()
+            .append("sessionId", (*session).client_session_id);
+        true
+    })
 }
 
 #[no_mangle]
@@ -81,10 +146,12 @@ pub unsafe extern "C" fn mongoc_client_session_get_operation_time(
     timestamp: *mut u32,
     increment: *mut u32,
 ) {
-    if let Some(ts) = (*session).rust_session.operation_time() {
-        *timestamp = ts.time;
-        *increment = ts.increment;
-    }
+    catch_panic(std::ptr::null_mut(), || {
+        if let Some(ts) = (*session).rust_session.operation_time() {
+            *timestamp = ts.time;
+            *increment = ts.increment;
+        }
+    })
 }
 
 #[no_mangle]
@@ -93,21 +160,23 @@ pub unsafe extern "C" fn mongoc_client_session_advance_operation_time(
     time: u32,
     increment: u32,
 ) {
-    (*session).advance_operation_time(Timestamp { time, increment })
+    catch_panic(std::ptr::null_mut(), || {
+        (*session).advance_operation_time(Timestamp { time, increment })
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_get_cluster_time(
     session: *mut mongoc_client_session_t,
 ) -> *const bson_t<'static> {
-    match (*session).cluster_time() {
+    catch_panic(std::ptr::null_mut(), || match (*session).cluster_time() {
         Some(ct) => {
             let doc = mongodb::bson::to_raw_document_buf(ct).unwrap();
             (*session).cluster_time = doc.into();
             &(*session).cluster_time
         }
         None => std::ptr::null(),
-    }
+    })
 }
 
 #[no_mangle]
@@ -115,63 +184,128 @@ pub unsafe extern "C" fn mongoc_client_session_advance_cluster_time(
     session: *mut mongoc_client_session_t,
     cluster_time: *const bson_t<'static>,
 ) {
-    let ct: ClusterTime = mongodb::bson::from_slice((*cluster_time).as_bytes()).unwrap();
-    (*session).advance_cluster_time(&ct)
+    catch_panic(std::ptr::null_mut(), || {
+        let ct: ClusterTime = mongodb::bson::from_slice((*cluster_time).as_bytes()).unwrap();
+        (*session).advance_cluster_time(&ct)
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_start_transaction(
     session: *mut mongoc_client_session_t,
     opts: *const mongoc_transaction_opt_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    (*session)
-        .rust_session
-        .start_transaction((*opts).clone())
-        .is_ok()
+    catch_panic(error, || {
+        let opts = if opts.is_null() {
+            None
+        } else {
+            Some((*opts).rust_opts.clone())
+        };
+
+        match (*session).rust_session.start_transaction(opts) {
+            Ok(()) => {
+                (*session).in_transaction = true;
+                true
+            }
+            Err(e) => {
+                set_error(error, (*session).error_api, &e.into());
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_abort_transaction(
     session: *mut mongoc_client_session_t,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    (*session).rust_session.abort_transaction().is_ok()
+    catch_panic(error, || {
+        (*session).in_transaction = false;
+        match (*session).rust_session.abort_transaction() {
+            Ok(()) => true,
+            Err(e) => {
+                set_error(error, (*session).error_api, &e.into());
+                false
+            }
+        }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_commit_transaction(
     session: *mut mongoc_client_session_t,
     _reply: *mut bson_t<'static>,
-    _error: *mut bson_error_t,
+    error: *mut bson_error_t,
 ) -> bool {
-    (*session).rust_session.commit_transaction().is_ok()
+    catch_panic(error, || {
+        (*session).in_transaction = false;
+        match (*session).rust_session.commit_transaction() {
+            Ok(()) => true,
+            Err(e) => {
+                set_error(error, (*session).error_api, &e.into());
+                false
+            }
+        }
+    })
 }
 
+/// The driver doesn't expose which mongos a session is pinned to, so this logs an error and
+/// returns 0, what libmongoc returns for an unpinned session.
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_get_server_id(
     _session: *mut mongoc_client_session_t,
 ) -> u32 {
-    panic!("cant get server id from rust session")
-}
-
+    catch_panic(std::ptr::null_mut(), || {
+        log_unsupported(
+            "mongoc_client_session_get_server_id",
+            "the driver doesn't expose the server a session is pinned to",
+        );
+        0
+    })
+}
+
+/// Reports MONGOC_TRANSACTION_IN_PROGRESS between starting a transaction and committing or
+/// aborting it, and MONGOC_TRANSACTION_NONE otherwise. The driver doesn't expose whether a
+/// transaction has run a command yet or how it ended, so MONGOC_TRANSACTION_STARTING,
+/// MONGOC_TRANSACTION_COMMITTED and MONGOC_TRANSACTION_ABORTED are never reported.
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_get_transaction_state(
-    _session: *mut mongoc_client_session_t,
+    session: *mut mongoc_client_session_t,
 ) -> mongoc_transaction_state_t {
-    panic!("rust doesn't expose transaction state")
+    catch_panic(std::ptr::null_mut(), || {
+        if (*session).in_transaction {
+            mongoc_transaction_state_t::MONGOC_TRANSACTION_IN_PROGRESS
+        } else {
+            mongoc_transaction_state_t::MONGOC_TRANSACTION_NONE
+        }
+    })
 }
 
+/// The driver doesn't expose whether a session has seen a network error, so this logs an error
+/// and returns false.
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_get_dirty(
     _session: *mut mongoc_client_session_t,
 ) -> bool {
-    panic!("rust doesn't expose dirty state")
+    catch_panic(std::ptr::null_mut(), || {
+        log_unsupported(
+            "mongoc_client_session_get_dirty",
+            "the driver doesn't expose whether a session is dirty",
+        );
+        false
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_client_session_destroy(session: *mut mongoc_client_session_t) {
-    drop(Box::from_raw(session))
+    catch_panic(std::ptr::null_mut(), || {
+        if let Some(sessions) = SESSIONS.lock().unwrap().as_mut() {
+            sessions.remove(&(*session).client_session_id);
+        }
+        drop(Box::from_raw(session))
+    })
 }
 
 pub struct mongoc_session_opt_t {
@@ -202,7 +336,9 @@ impl DerefMut for mongoc_session_opt_t {
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_session_opts_new() -> *mut mongoc_session_opt_t {
-    Box::into_raw(Box::new(mongoc_session_opt_t::new()))
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_session_opt_t::new()))
+    })
 }
 
 #[no_mangle]
@@ -210,7 +346,9 @@ pub unsafe extern "C" fn mongoc_session_opts_set_causal_consistency(
     opts: *mut mongoc_session_opt_t,
     cc: bool,
 ) {
-    (*opts).causal_consistency = Some(cc);
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).causal_consistency = Some(cc);
+    })
 }
 
 #[no_mangle]
@@ -218,7 +356,9 @@ pub unsafe extern "C" fn mongoc_session_opts_set_snapshot(
     opts: *mut mongoc_session_opt_t,
     snapshot: bool,
 ) {
-    (*opts).snapshot = Some(snapshot);
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).snapshot = Some(snapshot);
+    })
 }
 
 #[no_mangle]
@@ -226,12 +366,14 @@ pub unsafe extern "C" fn mongoc_session_opts_set_default_transaction_opts(
     opts: *mut mongoc_session_opt_t,
     txn_opts: *const mongoc_transaction_opt_t,
 ) {
-    (*opts).default_transaction_options = Some((*txn_opts).clone());
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).default_transaction_options = Some((*txn_opts).clone());
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_session_opts_destroy(opts: *mut mongoc_session_opt_t) {
-    drop(Box::from_raw(opts))
+    catch_panic(std::ptr::null_mut(), || drop(Box::from_raw(opts)))
 }
 
 pub struct mongoc_transaction_opt_t {
@@ -262,15 +404,23 @@ impl DerefMut for mongoc_transaction_opt_t {
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_transaction_opts_new() -> *mut mongoc_transaction_opt_t {
-    Box::into_raw(Box::new(mongoc_transaction_opt_t::new()))
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_transaction_opt_t::new()))
+    })
 }
 
+/// A `commit_time` of zero or less unsets the limit, like libmongoc's default of 0.
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_transaction_opts_set_max_commit_time_ms(
     opts: *mut mongoc_transaction_opt_t,
     commit_time: i64,
 ) {
-    (*opts).max_commit_time = Some(Duration::from_millis(commit_time.try_into().unwrap()));
+    catch_panic(std::ptr::null_mut(), || {
+        (*opts).max_commit_time = u64::try_from(commit_time)
+            .ok()
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis);
+    })
 }
 
 #[no_mangle]
@@ -278,7 +428,9 @@ pub unsafe extern "C" fn mongoc_transaction_opts_set_read_concern(
     _opts: *mut mongoc_transaction_opt_t,
     _rc: *const mongoc_read_concern_t,
 ) {
-    todo!("txn opts read concern not implemented")
+    catch_panic(std::ptr::null_mut(), || {
+        todo!("txn opts read concern not implemented")
+    })
 }
 
 #[no_mangle]
@@ -286,7 +438,9 @@ pub unsafe extern "C" fn mongoc_transaction_opts_set_write_concern(
     _opts: *mut mongoc_transaction_opt_t,
     _rc: *const mongoc_write_concern_t,
 ) {
-    todo!("txn opts write concern not implemented")
+    catch_panic(std::ptr::null_mut(), || {
+        todo!("txn opts write concern not implemented")
+    })
 }
 
 #[no_mangle]
@@ -294,10 +448,12 @@ pub unsafe extern "C" fn mongoc_transaction_opts_set_read_prefs(
     _opts: *mut mongoc_transaction_opt_t,
     _rc: *const mongoc_read_prefs_t,
 ) {
-    todo!("txn opts read prefs not implemented")
+    catch_panic(std::ptr::null_mut(), || {
+        todo!("txn opts read prefs not implemented")
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_transaction_opts_destroy(opts: *mut mongoc_transaction_opt_t) {
-    drop(Box::from_raw(opts))
+    catch_panic(std::ptr::null_mut(), || drop(Box::from_raw(opts)))
 }
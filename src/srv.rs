@@ -0,0 +1,427 @@
+use std::{
+    collections::hash_map::RandomState,
+    ffi::CStr,
+    hash::{BuildHasher, Hasher},
+    os::raw::{c_char, c_void},
+};
+
+use crate::{
+    bson::bson_error_t,
+    error::{catch_panic, mongoc_error_code_t, mongoc_error_domain_t, MongocError},
+};
+
+pub const MONGOC_DEFAULT_SRV_SERVICE_NAME: &str = "mongodb";
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum mongoc_rr_type_t {
+    MONGOC_RR_SRV,
+    MONGOC_RR_TXT,
+}
+
+/// The records found by a DNS resolver, which it adds with mongoc_rr_data_add_srv and
+/// mongoc_rr_data_add_txt.
+#[derive(Default)]
+pub struct mongoc_rr_data_t {
+    srv: Vec<(String, u16)>,
+    txt: Vec<String>,
+}
+
+/// Looks up the SRV or TXT records of `name` and adds them to `records`. On failure it returns
+/// false and may describe the error in `error`.
+#[allow(non_camel_case_types)]
+pub type mongoc_rust_dns_resolver_t = Option<
+    unsafe extern "C" fn(
+        context: *mut c_void,
+        rr_type: mongoc_rr_type_t,
+        name: *const c_char,
+        records: *mut mongoc_rr_data_t,
+        error: *mut bson_error_t,
+    ) -> bool,
+>;
+
+/// A resolver set with mongoc_rust_uri_set_dns_resolver and its context, which is kept as an
+/// address so URIs holding one can still be shared between threads.
+#[derive(Clone, Copy)]
+pub(crate) struct DnsResolver {
+    resolver: unsafe extern "C" fn(
+        *mut c_void,
+        mongoc_rr_type_t,
+        *const c_char,
+        *mut mongoc_rr_data_t,
+        *mut bson_error_t,
+    ) -> bool,
+    context: usize,
+}
+
+impl DnsResolver {
+    /// Returns None if `resolver` is NULL, so the driver's own DNS resolution is used.
+    pub(crate) fn new(resolver: mongoc_rust_dns_resolver_t, context: *mut c_void) -> Option<Self> {
+        resolver.map(|resolver| Self {
+            resolver,
+            context: context as usize,
+        })
+    }
+
+    /// Looks up the records of `name`, turning a failed lookup into an error.
+    fn lookup(&self, rr_type: mongoc_rr_type_t, name: &str) -> anyhow::Result<mongoc_rr_data_t> {
+        let c_name = std::ffi::CString::new(name)?;
+        let mut records = mongoc_rr_data_t::default();
+        let mut error = bson_error_t {
+            domain: 0,
+            code: 0,
+            message: [0; 504],
+        };
+        let found = unsafe {
+            (self.resolver)(
+                self.context as *mut c_void,
+                rr_type,
+                c_name.as_ptr(),
+                &mut records,
+                &mut error,
+            )
+        };
+        if found {
+            return Ok(records);
+        }
+
+        let message = unsafe { CStr::from_ptr(error.message.as_ptr()) }.to_string_lossy();
+        Err(if error.domain == 0 {
+            name_resolution_error(format!(
+                "Failed to look up {:?} record \"{}\"",
+                rr_type, name
+            ))
+        } else {
+            MongocError {
+                domain: error.domain,
+                code: error.code,
+                message: message.into_owned(),
+            }
+            .into()
+        })
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_rr_data_add_srv(
+    records: *mut mongoc_rr_data_t,
+    target: *const c_char,
+    port: u16,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        if target.is_null() {
+            return false;
+        }
+
+        let target = CStr::from_ptr(target).to_string_lossy();
+        (*records).srv.push((target.into_owned(), port));
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_rr_data_add_txt(
+    records: *mut mongoc_rr_data_t,
+    txt: *const c_char,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        if txt.is_null() {
+            return false;
+        }
+
+        let txt = CStr::from_ptr(txt).to_string_lossy();
+        (*records).txt.push(txt.into_owned());
+        true
+    })
+}
+
+/// The seed list and options found by resolving a mongodb+srv URI.
+pub(crate) struct SrvResult {
+    pub(crate) hosts: Vec<(String, u16)>,
+    /// The options of the TXT record, as `key=value` pairs.
+    pub(crate) txt_options: Vec<(String, String)>,
+}
+
+/// Resolves the SRV hostname of a mongodb+srv URI with `resolver`. At most `max_hosts` hosts are
+/// chosen at random, if it's positive.
+pub(crate) fn resolve(
+    resolver: DnsResolver,
+    hostname: &str,
+    service_name: &str,
+    max_hosts: i32,
+) -> anyhow::Result<SrvResult> {
+    // The SRV records must be in the same domain as the hostname, which must have at least
+    // three parts, e.g. "cluster0.example.com".
+    let parts: Vec<&str> = hostname.split('.').collect();
+    if parts.len() < 3 || parts.iter().any(|part| part.is_empty()) {
+        return Err(name_resolution_error(format!(
+            "Invalid SRV hostname \"{}\"",
+            hostname
+        )));
+    }
+    let domain = format!(".{}", parts[1..].join("."));
+
+    let srv = resolver.lookup(
+        mongoc_rr_type_t::MONGOC_RR_SRV,
+        &format!("_{}._tcp.{}", service_name, hostname),
+    )?;
+    let mut hosts = Vec::with_capacity(srv.srv.len());
+    for (target, port) in srv.srv {
+        let target = target.trim_end_matches('.').to_lowercase();
+        if !target.ends_with(&domain) {
+            return Err(name_resolution_error(format!(
+                "Invalid host \"{}\" returned for service \"{}\": host must be subdomain of \
+                 service name",
+                target, hostname
+            )));
+        }
+        hosts.push((target, port));
+    }
+    if hosts.is_empty() {
+        return Err(name_resolution_error(format!(
+            "No SRV records found for \"{}\"",
+            hostname
+        )));
+    }
+    if max_hosts > 0 && hosts.len() > max_hosts as usize {
+        shuffle(&mut hosts);
+        hosts.truncate(max_hosts as usize);
+    }
+
+    // As in libmongoc, a missing TXT record, or one that can't be looked up, just means there
+    // are no options to add.
+    let txt = resolver
+        .lookup(mongoc_rr_type_t::MONGOC_RR_TXT, hostname)
+        .unwrap_or_default();
+    if txt.txt.len() > 1 {
+        return Err(name_resolution_error(format!(
+            "Multiple TXT records for \"{}\"",
+            hostname
+        )));
+    }
+    let mut txt_options = Vec::new();
+    for pair in txt.txt.iter().flat_map(|txt| txt.split('&')) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid TXT record option \"{}\"", pair))?;
+        // Only these options may be set by a TXT record.
+        if !["authsource", "replicaset", "loadbalanced"].contains(&key.to_lowercase().as_str()) {
+            anyhow::bail!("Invalid TXT record option \"{}\"", key);
+        }
+        txt_options.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(SrvResult { hosts, txt_options })
+}
+
+fn name_resolution_error(message: String) -> anyhow::Error {
+    MongocError::new(
+        mongoc_error_domain_t::MONGOC_ERROR_STREAM,
+        mongoc_error_code_t::MONGOC_ERROR_STREAM_NAME_RESOLUTION,
+        message,
+    )
+    .into()
+}
+
+/// Shuffles `items` with a Fisher-Yates shuffle, seeded from the randomly keyed hasher std
+/// provides so no RNG dependency is needed.
+fn shuffle<T>(items: &mut [T]) {
+    let state = RandomState::new();
+    for i in (1..items.len()).rev() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        let j = (hasher.finish() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, ffi::CString};
+
+    use super::*;
+
+    /// The records a stub resolver serves. A TXT lookup fails if `txt` is None.
+    #[derive(Default)]
+    struct Records {
+        srv: Vec<(&'static str, u16)>,
+        txt: Option<Vec<&'static str>>,
+        lookups: RefCell<Vec<String>>,
+    }
+
+    unsafe extern "C" fn stub_resolver(
+        context: *mut c_void,
+        rr_type: mongoc_rr_type_t,
+        name: *const c_char,
+        records: *mut mongoc_rr_data_t,
+        _error: *mut bson_error_t,
+    ) -> bool {
+        let stub = &*(context as *const Records);
+        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+        stub.lookups.borrow_mut().push(name);
+        match rr_type {
+            mongoc_rr_type_t::MONGOC_RR_SRV => {
+                for (target, port) in &stub.srv {
+                    let target = CString::new(*target).unwrap();
+                    mongoc_rr_data_add_srv(records, target.as_ptr(), *port);
+                }
+                true
+            }
+            mongoc_rr_type_t::MONGOC_RR_TXT => match stub.txt {
+                Some(ref txt) => {
+                    for txt in txt {
+                        let txt = CString::new(*txt).unwrap();
+                        mongoc_rr_data_add_txt(records, txt.as_ptr());
+                    }
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    fn resolve_with(stub: &Records, hostname: &str, max_hosts: i32) -> anyhow::Result<SrvResult> {
+        let resolver =
+            DnsResolver::new(Some(stub_resolver), stub as *const Records as *mut c_void).unwrap();
+        resolve(
+            resolver,
+            hostname,
+            MONGOC_DEFAULT_SRV_SERVICE_NAME,
+            max_hosts,
+        )
+    }
+
+    fn resolve_err(stub: &Records, hostname: &str) -> String {
+        match resolve_with(stub, hostname, 0) {
+            Ok(_) => panic!("expected resolving \"{}\" to fail", hostname),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn resolves_hosts_and_txt_options() {
+        let stub = Records {
+            srv: vec![
+                ("Host1.Example.COM.", 27017),
+                ("host2.sub.example.com", 27018),
+            ],
+            txt: Some(vec!["replicaSet=rs0&authSource=admin"]),
+            ..Default::default()
+        };
+        let resolved = resolve_with(&stub, "cluster0.example.com", 0).unwrap();
+
+        assert_eq!(
+            resolved.hosts,
+            vec![
+                ("host1.example.com".to_string(), 27017),
+                ("host2.sub.example.com".to_string(), 27018),
+            ]
+        );
+        assert_eq!(
+            resolved.txt_options,
+            vec![
+                ("replicaSet".to_string(), "rs0".to_string()),
+                ("authSource".to_string(), "admin".to_string()),
+            ]
+        );
+        assert_eq!(
+            *stub.lookups.borrow(),
+            vec!["_mongodb._tcp.cluster0.example.com", "cluster0.example.com"]
+        );
+    }
+
+    #[test]
+    fn hosts_must_share_the_domain() {
+        for target in ["host.evilexample.com", "example.com", "host.example.org"] {
+            let stub = Records {
+                srv: vec![(target, 27017)],
+                txt: Some(vec![]),
+                ..Default::default()
+            };
+            assert_eq!(
+                resolve_err(&stub, "cluster0.example.com"),
+                format!(
+                    "Invalid host \"{}\" returned for service \"cluster0.example.com\": host \
+                     must be subdomain of service name",
+                    target
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn hostname_needs_three_parts() {
+        let stub = Records::default();
+        for hostname in ["example.com", "a..com", "localhost"] {
+            assert_eq!(
+                resolve_err(&stub, hostname),
+                format!("Invalid SRV hostname \"{}\"", hostname)
+            );
+        }
+        assert!(stub.lookups.borrow().is_empty());
+    }
+
+    #[test]
+    fn no_srv_records_is_an_error() {
+        let stub = Records {
+            txt: Some(vec![]),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_err(&stub, "cluster0.example.com"),
+            "No SRV records found for \"cluster0.example.com\""
+        );
+    }
+
+    #[test]
+    fn failed_txt_lookup_means_no_options() {
+        let stub = Records {
+            srv: vec![("host1.example.com", 27017)],
+            txt: None,
+            ..Default::default()
+        };
+        let resolved = resolve_with(&stub, "cluster0.example.com", 0).unwrap();
+        assert_eq!(resolved.hosts.len(), 1);
+        assert!(resolved.txt_options.is_empty());
+    }
+
+    #[test]
+    fn invalid_txt_records_are_errors() {
+        let mut stub = Records {
+            srv: vec![("host1.example.com", 27017)],
+            txt: Some(vec!["replicaSet=rs0", "authSource=admin"]),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_err(&stub, "cluster0.example.com"),
+            "Multiple TXT records for \"cluster0.example.com\""
+        );
+
+        stub.txt = Some(vec!["ssl=false"]);
+        assert_eq!(
+            resolve_err(&stub, "cluster0.example.com"),
+            "Invalid TXT record option \"ssl\""
+        );
+    }
+
+    #[test]
+    fn max_hosts_limits_the_seed_list() {
+        let stub = Records {
+            srv: vec![
+                ("host1.example.com", 27017),
+                ("host2.example.com", 27017),
+                ("host3.example.com", 27017),
+            ],
+            txt: Some(vec![]),
+            ..Default::default()
+        };
+        let resolved = resolve_with(&stub, "cluster0.example.com", 2).unwrap();
+        assert_eq!(resolved.hosts.len(), 2);
+        assert!(resolved
+            .hosts
+            .iter()
+            .all(|(host, _)| stub.srv.iter().any(|(target, _)| target == host)));
+        assert_ne!(resolved.hosts[0], resolved.hosts[1]);
+    }
+}
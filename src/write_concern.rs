@@ -1,18 +1,27 @@
 use std::{
-    ffi::CStr,
+    ffi::{CStr, CString},
     ops::{Deref, DerefMut},
     os::raw::c_char,
     time::Duration,
 };
 
-use mongodb::options::{Acknowledgment, WriteConcern};
+use mongodb::{
+    bson::RawDocumentBuf,
+    options::{Acknowledgment, WriteConcern},
+};
+
+use crate::{bson::bson_t, error::catch_panic};
 
+pub const MONGOC_WRITE_CONCERN_W_UNACKNOWLEDGED: i32 = 0;
 pub const MONGOC_WRITE_CONCERN_W_DEFAULT: i32 = -2;
 pub const MONGOC_WRITE_CONCERN_W_MAJORITY: i32 = -3;
 pub const MONGOC_WRITE_CONCERN_W_TAG: i32 = -4;
 
+#[derive(Clone, Default)]
 pub struct mongoc_write_concern_t {
     rust_write_concern: WriteConcern,
+    /// The tag of a `w: <tag>` write concern, kept for mongoc_write_concern_get_wtag.
+    wtag: Option<CString>,
 }
 
 impl Deref for mongoc_write_concern_t {
@@ -31,31 +40,96 @@ impl DerefMut for mongoc_write_concern_t {
 
 impl From<WriteConcern> for mongoc_write_concern_t {
     fn from(wc: WriteConcern) -> Self {
-        Self {
+        let w = wc.w.clone();
+        let mut wc = Self {
             rust_write_concern: wc,
+            wtag: None,
+        };
+        wc.set_acknowledgment(w);
+        wc
+    }
+}
+
+impl mongoc_write_concern_t {
+    /// The write concern to configure the driver with, or None if nothing has been set so the
+    /// server's default applies.
+    pub(crate) fn rust_write_concern(&self) -> Option<&WriteConcern> {
+        let wc = &self.rust_write_concern;
+        if wc.w.is_none() && wc.w_timeout.is_none() && wc.journal.is_none() {
+            None
+        } else {
+            Some(wc)
+        }
+    }
+
+    fn set_acknowledgment(&mut self, w: Option<Acknowledgment>) {
+        self.wtag = match w {
+            Some(Acknowledgment::Custom(ref tag)) => CString::new(tag.as_str()).ok(),
+            _ => None,
+        };
+        self.rust_write_concern.w = w;
+    }
+
+    fn is_acknowledged(&self) -> bool {
+        !matches!(self.w, Some(Acknowledgment::Nodes(0))) || self.journal == Some(true)
+    }
+
+    /// An unacknowledged write can't wait for the journal, as in libmongoc.
+    pub(crate) fn is_valid(&self) -> bool {
+        !(matches!(self.w, Some(Acknowledgment::Nodes(0))) && self.journal == Some(true))
+    }
+
+    /// The write concern document sent to the server, e.g. `{w: "majority", wtimeout: 100}`.
+    fn to_raw_document(&self) -> RawDocumentBuf {
+        let mut doc = RawDocumentBuf::new();
+        match self.w {
+            Some(Acknowledgment::Nodes(n)) => doc.append("w", n as i32),
+            Some(Acknowledgment::Majority) => doc.append("w", "majority"),
+            Some(Acknowledgment::Custom(ref tag)) => doc.append("w", tag.as_str()),
+            _ => {}
         }
+        if let Some(journal) = self.journal {
+            doc.append("j", journal);
+        }
+        match self.w_timeout {
+            Some(wtimeout) if !wtimeout.is_zero() => {
+                doc.append("wtimeout", wtimeout.as_millis() as i64)
+            }
+            _ => {}
+        }
+        doc
     }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_new() -> *mut mongoc_write_concern_t {
-    Box::into_raw(Box::new(mongoc_write_concern_t {
-        rust_write_concern: Default::default(),
-    }))
+    catch_panic(std::ptr::null_mut(), || {
+        Box::into_raw(Box::new(mongoc_write_concern_t::default()))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_copy(
+    wc: *const mongoc_write_concern_t,
+) -> *mut mongoc_write_concern_t {
+    catch_panic(std::ptr::null_mut(), || match wc.as_ref() {
+        Some(wc) => Box::into_raw(Box::new(wc.clone())),
+        None => std::ptr::null_mut(),
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_journal_is_set(
     wc: *const mongoc_write_concern_t,
 ) -> bool {
-    (*wc).journal.is_some()
+    catch_panic(std::ptr::null_mut(), || (*wc).journal.is_some())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_get_journal(
     wc: *const mongoc_write_concern_t,
 ) -> bool {
-    (*wc).journal.unwrap_or(true)
+    catch_panic(std::ptr::null_mut(), || (*wc).journal.unwrap_or(true))
 }
 
 #[no_mangle]
@@ -63,19 +137,21 @@ pub unsafe extern "C" fn mongoc_write_concern_set_journal(
     wc: *mut mongoc_write_concern_t,
     journal: bool,
 ) -> bool {
-    (*wc).journal = Some(journal);
-    true
+    catch_panic(std::ptr::null_mut(), || {
+        (*wc).journal = Some(journal);
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_get_w(wc: *const mongoc_write_concern_t) -> i32 {
-    match &(*wc).w {
+    catch_panic(std::ptr::null_mut(), || match &(*wc).w {
         Some(Acknowledgment::Majority) => MONGOC_WRITE_CONCERN_W_MAJORITY,
         Some(Acknowledgment::Nodes(n)) => *n as i32,
         Some(Acknowledgment::Custom(_)) => MONGOC_WRITE_CONCERN_W_TAG,
         None => MONGOC_WRITE_CONCERN_W_DEFAULT,
         _ => unreachable!(),
-    }
+    })
 }
 
 #[no_mangle]
@@ -83,19 +159,26 @@ pub unsafe extern "C" fn mongoc_write_concern_set_w(
     wc: *mut mongoc_write_concern_t,
     w: i32,
 ) -> bool {
-    if w >= 0 {
-        (*wc).w = Acknowledgment::Nodes(w as u32).into();
+    catch_panic(std::ptr::null_mut(), || {
+        let w = match w {
+            MONGOC_WRITE_CONCERN_W_DEFAULT => None,
+            MONGOC_WRITE_CONCERN_W_MAJORITY => Some(Acknowledgment::Majority),
+            w if w >= 0 => Some(Acknowledgment::Nodes(w as u32)),
+            _ => return false,
+        };
+        (*wc).set_acknowledgment(w);
         true
-    } else {
-        false
-    }
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_get_wtag(
     wc: *const mongoc_write_concern_t,
 ) -> *const c_char {
-    todo!()
+    catch_panic(std::ptr::null_mut(), || match (*wc).wtag {
+        Some(ref tag) => tag.as_ptr(),
+        None => std::ptr::null(),
+    })
 }
 
 #[no_mangle]
@@ -103,16 +186,66 @@ pub unsafe extern "C" fn mongoc_write_concern_set_wtag(
     wc: *mut mongoc_write_concern_t,
     tag: *const c_char,
 ) -> bool {
-    let tag = CStr::from_ptr(tag).to_string_lossy();
-    (*wc).w = Acknowledgment::Custom(tag.to_string()).into();
-    true
+    catch_panic(std::ptr::null_mut(), || {
+        if tag.is_null() {
+            return false;
+        }
+
+        let tag = CStr::from_ptr(tag).to_string_lossy();
+        (*wc).set_acknowledgment(Acknowledgment::Custom(tag.to_string()).into());
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_get_wmajority(
+    wc: *const mongoc_write_concern_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        matches!((*wc).w, Some(Acknowledgment::Majority))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_set_wmajority(
+    wc: *mut mongoc_write_concern_t,
+    wtimeout_msec: i32,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*wc).set_acknowledgment(Some(Acknowledgment::Majority));
+        if wtimeout_msec >= 0 {
+            (*wc).w_timeout = Some(Duration::from_millis(wtimeout_msec as u64));
+        }
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_get_wtimeout(
+    wc: *const mongoc_write_concern_t,
+) -> i32 {
+    catch_panic(std::ptr::null_mut(), || {
+        mongoc_write_concern_get_wtimeout_int64(wc).clamp(0, i32::MAX as i64) as i32
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_set_wtimeout(
+    wc: *mut mongoc_write_concern_t,
+    wtimeout_msec: i32,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        mongoc_write_concern_set_wtimeout_int64(wc, wtimeout_msec as i64)
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_get_wtimeout_int64(
     wc: *const mongoc_write_concern_t,
 ) -> i64 {
-    (*wc).w_timeout.map(|d| d.as_millis() as i64).unwrap_or(-1)
+    catch_panic(std::ptr::null_mut(), || {
+        (*wc).w_timeout.map(|d| d.as_millis() as i64).unwrap_or(0)
+    })
 }
 
 #[no_mangle]
@@ -120,11 +253,66 @@ pub unsafe extern "C" fn mongoc_write_concern_set_wtimeout_int64(
     wc: *mut mongoc_write_concern_t,
     wtimeout: i64,
 ) -> bool {
-    (*wc).w_timeout = Some(Duration::from_secs(wtimeout as u64));
-    true
+    catch_panic(std::ptr::null_mut(), || {
+        if wtimeout < 0 {
+            return false;
+        }
+
+        (*wc).w_timeout = Some(Duration::from_millis(wtimeout as u64));
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_is_acknowledged(
+    wc: *const mongoc_write_concern_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || match wc.as_ref() {
+        Some(wc) => wc.is_acknowledged(),
+        None => true,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_is_valid(wc: *const mongoc_write_concern_t) -> bool {
+    catch_panic(std::ptr::null_mut(), || match wc.as_ref() {
+        Some(wc) => wc.is_valid(),
+        None => false,
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_is_default(
+    wc: *const mongoc_write_concern_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        (*wc).rust_write_concern().is_none()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn mongoc_write_concern_append(
+    wc: *const mongoc_write_concern_t,
+    command: *mut bson_t,
+) -> bool {
+    catch_panic(std::ptr::null_mut(), || {
+        if !(*wc).is_valid() {
+            return false;
+        }
+
+        (*command)
+            .doc
+            .to_mut()
+            .append("writeConcern", (*wc).to_raw_document());
+        true
+    })
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn mongoc_write_concern_destroy(wc: *mut mongoc_write_concern_t) {
-    drop(Box::from_raw(wc))
+    catch_panic(std::ptr::null_mut(), || {
+        if !wc.is_null() {
+            drop(Box::from_raw(wc))
+        }
+    })
 }